@@ -0,0 +1,41 @@
+//! Convert a recording of `m5stickc::recorder::Recorder` to CSV.
+//!
+//! cargo run --example decode_recording --no-default-features --features std,m5stickc \
+//!   --target x86_64-unknown-linux-gnu -- recording.bin > recording.csv
+
+use std::io::Read;
+use std::process::ExitCode;
+
+use m5stickc::recorder;
+
+fn main() -> ExitCode {
+  let mut bytes = Vec::new();
+  let read = match std::env::args().nth(1) {
+    Some(path) => std::fs::File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)),
+    None => std::io::stdin().read_to_end(&mut bytes),
+  };
+  if let Err(error) = read {
+    eprintln!("Cannot read the recording: {}", error);
+    return ExitCode::FAILURE;
+  }
+
+  let decoded = match recorder::decode(&bytes) {
+    Ok(decoded) => decoded,
+    Err(error) => {
+      eprintln!("Cannot decode the recording: {:?}", error);
+      return ExitCode::FAILURE;
+    }
+  };
+  if let Some(error) = decoded.error {
+    eprintln!(
+      "Ignoring {} bytes after a bad block: {:?}",
+      bytes.len() - decoded.valid_len,
+      error
+    );
+  }
+
+  let mut csv = String::new();
+  recorder::write_csv(&decoded.records, &mut csv).unwrap();
+  print!("{}", csv);
+  ExitCode::SUCCESS
+}
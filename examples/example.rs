@@ -13,6 +13,8 @@ use embedded_graphics::{
 use esp_idf_hal::peripherals::Peripherals;
 use esp_idf_svc::timer::EspTaskTimerService;
 use m5stickc::display_buffer;
+use m5stickc::imu::Imu;
+use m5stickc::vector::AxisRemap;
 
 #[no_mangle]
 fn main() {
@@ -26,7 +28,11 @@ fn main() {
   let peripherals = Peripherals::take().unwrap();
 
   let mut m5 = m5stickc::new_m5!(peripherals).unwrap();
-  m5.imu().init().unwrap();
+  m5.imu().init(&mut esp_idf_hal::delay::Ets).unwrap();
+  // Keep the device still while it boots, otherwise the gyroscope stays uncalibrated
+  if let Some(mpu6886) = m5.imu().as_mpu6886() {
+    let _ = mpu6886.calibrate_gyro(200, &mut esp_idf_hal::delay::Ets);
+  }
 
   let mut canvas = display_buffer::DisplayBuffer::new(
     Rgb565::BLACK,
@@ -48,21 +54,20 @@ fn main() {
     writeln!(canvas, "fps: {:.2}", fps).unwrap();
     prev = now;
 
-    let gyro_result = m5.imu().get_gyro_data();
-    let accel_result = m5.imu().get_accel_data();
-
-    if gyro_result.is_ok() && accel_result.is_ok() {
-      let (gyro_x, gyro_y, gyro_z) = gyro_result.unwrap();
-      let (acc_x, acc_y, acc_z) = accel_result.unwrap();
+    if let Ok(motion) = m5.imu().get_motion_data() {
+      // Line the axes up with the screen
+      let gyro = AxisRemap::LCD.apply(motion.gyro);
+      let accel = AxisRemap::LCD.apply(motion.accel);
 
       writeln!(canvas, "  X       Y       Z").unwrap();
       writeln!(
         canvas,
         "{:.2}   {:.2}   {:.2}      o/s",
-        gyro_x, gyro_y, gyro_z
+        gyro.x, gyro.y, gyro.z
       )
       .unwrap();
-      writeln!(canvas, "{:.2}   {:.2}   {:.2}", acc_x, acc_y, acc_z).unwrap();
+      writeln!(canvas, "{:.2}   {:.2}   {:.2}", accel.x, accel.y, accel.z).unwrap();
+      writeln!(canvas, "{:.1} C", motion.temp_c).unwrap();
     } else {
       write!(canvas, "Sensor read error").unwrap();
     }
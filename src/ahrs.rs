@@ -0,0 +1,437 @@
+use libm::{asinf, atan2f, cosf, sinf, sqrtf};
+
+use crate::mpu6886::MotionSample;
+use crate::vector::{Acceleration, AngularRate, Vector3};
+
+/// Orientation of the sensor relative to the earth frame, Z pointing up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+  pub w: f32,
+  pub x: f32,
+  pub y: f32,
+  pub z: f32,
+}
+
+/// Rotations in degrees, applied in yaw, pitch, roll order.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EulerAngles {
+  /// Around X.
+  pub roll: f32,
+  /// Around Y.
+  pub pitch: f32,
+  /// Around Z.
+  pub yaw: f32,
+}
+
+impl Quaternion {
+  pub const IDENTITY: Quaternion = Quaternion {
+    w: 1.0,
+    x: 0.0,
+    y: 0.0,
+    z: 0.0,
+  };
+
+  pub fn from_euler(angles: EulerAngles) -> Self {
+    let half = |degrees: f32| degrees.to_radians() / 2.0;
+    let (sr, cr) = (sinf(half(angles.roll)), cosf(half(angles.roll)));
+    let (sp, cp) = (sinf(half(angles.pitch)), cosf(half(angles.pitch)));
+    let (sy, cy) = (sinf(half(angles.yaw)), cosf(half(angles.yaw)));
+    Self {
+      w: cr * cp * cy + sr * sp * sy,
+      x: sr * cp * cy - cr * sp * sy,
+      y: cr * sp * cy + sr * cp * sy,
+      z: cr * cp * sy - sr * sp * cy,
+    }
+  }
+
+  /// Orientation of a sensor at rest measuring `accel`, with a yaw of 0.
+  ///
+  /// Starting a filter from this avoids waiting for it to converge from `IDENTITY`.
+  pub fn from_accel(accel: Acceleration) -> Self {
+    let Acceleration {
+      x: ax,
+      y: ay,
+      z: az,
+    } = accel;
+    Self::from_euler(EulerAngles {
+      roll: atan2f(ay, az).to_degrees(),
+      pitch: atan2f(-ax, sqrtf(ay * ay + az * az)).to_degrees(),
+      yaw: 0.0,
+    })
+  }
+
+  pub fn euler(&self) -> EulerAngles {
+    let Self { w, x, y, z } = *self;
+    EulerAngles {
+      roll: atan2f(w * x + y * z, 0.5 - x * x - y * y).to_degrees(),
+      pitch: asinf((2.0 * (w * y - x * z)).clamp(-1.0, 1.0)).to_degrees(),
+      yaw: atan2f(w * z + x * y, 0.5 - y * y - z * z).to_degrees(),
+    }
+  }
+
+  /// Direction of gravity in the sensor frame, as the accelerometer measures it at rest.
+  pub fn gravity(&self) -> Vector3 {
+    let Self { w, x, y, z } = *self;
+    Vector3::new(
+      2.0 * (x * z - w * y),
+      2.0 * (w * x + y * z),
+      w * w - x * x - y * y + z * z,
+    )
+  }
+
+  fn normalized(self) -> Self {
+    let norm = sqrtf(self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z);
+    if norm == 0.0 {
+      return Self::IDENTITY;
+    }
+    Self {
+      w: self.w / norm,
+      x: self.x / norm,
+      y: self.y / norm,
+      z: self.z / norm,
+    }
+  }
+
+  /// Integrate the angular rate in rad/s over `dt` seconds.
+  fn integrate(self, gyro: Vector3, dt: f32) -> Self {
+    let Self { w, x, y, z } = self;
+    let Vector3 {
+      x: gx,
+      y: gy,
+      z: gz,
+    } = gyro;
+    let half_dt = 0.5 * dt;
+    Self {
+      w: w + (-x * gx - y * gy - z * gz) * half_dt,
+      x: x + (w * gx + y * gz - z * gy) * half_dt,
+      y: y + (w * gy - x * gz + z * gx) * half_dt,
+      z: z + (w * gz + x * gy - y * gx) * half_dt,
+    }
+    .normalized()
+  }
+}
+
+impl Default for Quaternion {
+  fn default() -> Self {
+    Self::IDENTITY
+  }
+}
+
+/// Attitude estimation from accelerometer and gyroscope readings.
+///
+/// The filters only use `f32` arithmetic, so the same input gives the same output on the device
+/// and on a host.
+pub trait Ahrs {
+  /// Seconds between two calls to `update`.
+  fn sample_period(&self) -> f32;
+
+  /// Feed one sample taken `dt` seconds after the previous one.
+  fn update_dt(&mut self, accel: Acceleration, gyro: AngularRate, dt: f32);
+
+  fn quaternion(&self) -> Quaternion;
+
+  /// Restart the estimation from `quaternion`.
+  fn set_quaternion(&mut self, quaternion: Quaternion);
+
+  /// Feed one sample taken `sample_period` seconds after the previous one.
+  fn update(&mut self, accel: Acceleration, gyro: AngularRate) {
+    self.update_dt(accel, gyro, self.sample_period());
+  }
+
+  fn update_motion(&mut self, sample: &MotionSample) {
+    self.update(sample.accel, sample.gyro);
+  }
+
+  fn euler(&self) -> EulerAngles {
+    self.quaternion().euler()
+  }
+
+  fn gravity(&self) -> Vector3 {
+    self.quaternion().gravity()
+  }
+
+  fn reset(&mut self) {
+    self.set_quaternion(Quaternion::IDENTITY);
+  }
+}
+
+/// Mahony's complementary filter: gyroscope integration corrected towards the measured gravity by a
+/// PI controller.
+#[derive(Clone, Copy, Debug)]
+pub struct Mahony {
+  kp: f32,
+  ki: f32,
+  sample_period: f32,
+  quaternion: Quaternion,
+  integral: Vector3,
+}
+
+impl Mahony {
+  pub fn new(sample_period: f32) -> Self {
+    Self {
+      kp: 0.5,
+      ki: 0.0,
+      sample_period,
+      quaternion: Quaternion::IDENTITY,
+      integral: Vector3::ZERO,
+    }
+  }
+
+  /// Proportional gain, higher values trust the accelerometer more. Defaults to 0.5.
+  pub fn kp(mut self, kp: f32) -> Self {
+    self.kp = kp;
+    self
+  }
+
+  /// Integral gain, removes remaining gyroscope bias. Defaults to 0, i.e. off.
+  pub fn ki(mut self, ki: f32) -> Self {
+    self.ki = ki;
+    self
+  }
+}
+
+impl Ahrs for Mahony {
+  fn sample_period(&self) -> f32 {
+    self.sample_period
+  }
+
+  fn update_dt(&mut self, accel: Acceleration, gyro: AngularRate, dt: f32) {
+    let mut gyro = gyro.in_rad_per_s();
+
+    if let Some(accel) = accel.vector().normalized() {
+      // Error between measured and estimated gravity
+      let error = accel.cross(self.quaternion.gravity());
+
+      if self.ki > 0.0 {
+        self.integral = self.integral + error * (self.ki * dt);
+      } else {
+        self.integral = Vector3::ZERO;
+      }
+
+      gyro = gyro + error * self.kp + self.integral;
+    }
+
+    self.quaternion = self.quaternion.integrate(gyro, dt);
+  }
+
+  fn quaternion(&self) -> Quaternion {
+    self.quaternion
+  }
+
+  fn set_quaternion(&mut self, quaternion: Quaternion) {
+    self.quaternion = quaternion.normalized();
+    self.integral = Vector3::ZERO;
+  }
+}
+
+/// Madgwick's gradient descent filter.
+#[derive(Clone, Copy, Debug)]
+pub struct Madgwick {
+  beta: f32,
+  sample_period: f32,
+  quaternion: Quaternion,
+}
+
+impl Madgwick {
+  pub fn new(sample_period: f32) -> Self {
+    Self {
+      beta: 0.1,
+      sample_period,
+      quaternion: Quaternion::IDENTITY,
+    }
+  }
+
+  /// Gradient descent step, higher values trust the accelerometer more. Defaults to 0.1.
+  pub fn beta(mut self, beta: f32) -> Self {
+    self.beta = beta;
+    self
+  }
+}
+
+impl Ahrs for Madgwick {
+  fn sample_period(&self) -> f32 {
+    self.sample_period
+  }
+
+  fn update_dt(&mut self, accel: Acceleration, gyro: AngularRate, dt: f32) {
+    let Quaternion {
+      w: q0,
+      x: q1,
+      y: q2,
+      z: q3,
+    } = self.quaternion;
+    let Vector3 {
+      x: gx,
+      y: gy,
+      z: gz,
+    } = gyro.in_rad_per_s();
+
+    // Rate of change from the gyroscope
+    let mut dq = [
+      0.5 * (-q1 * gx - q2 * gy - q3 * gz),
+      0.5 * (q0 * gx + q2 * gz - q3 * gy),
+      0.5 * (q0 * gy - q1 * gz + q3 * gx),
+      0.5 * (q0 * gz + q1 * gy - q2 * gx),
+    ];
+
+    if let Some(Vector3 {
+      x: ax,
+      y: ay,
+      z: az,
+    }) = accel.vector().normalized()
+    {
+      // Gradient of the error between measured and estimated gravity
+      let (q0q0, q1q1, q2q2, q3q3) = (q0 * q0, q1 * q1, q2 * q2, q3 * q3);
+      let step = [
+        4.0 * q0 * q2q2 + 2.0 * q2 * ax + 4.0 * q0 * q1q1 - 2.0 * q1 * ay,
+        4.0 * q1 * q3q3 - 2.0 * q3 * ax + 4.0 * q0q0 * q1 - 2.0 * q0 * ay - 4.0 * q1
+          + 8.0 * q1 * q1q1
+          + 8.0 * q1 * q2q2
+          + 4.0 * q1 * az,
+        4.0 * q0q0 * q2 + 2.0 * q0 * ax + 4.0 * q2 * q3q3 - 2.0 * q3 * ay - 4.0 * q2
+          + 8.0 * q2 * q1q1
+          + 8.0 * q2 * q2q2
+          + 4.0 * q2 * az,
+        4.0 * q1q1 * q3 - 2.0 * q1 * ax + 4.0 * q2q2 * q3 - 2.0 * q2 * ay,
+      ];
+      let norm = sqrtf(step.iter().map(|s| s * s).sum());
+      if norm > 0.0 {
+        for (dq, s) in dq.iter_mut().zip(step) {
+          *dq -= self.beta * s / norm;
+        }
+      }
+    }
+
+    self.quaternion = Quaternion {
+      w: q0 + dq[0] * dt,
+      x: q1 + dq[1] * dt,
+      y: q2 + dq[2] * dt,
+      z: q3 + dq[3] * dt,
+    }
+    .normalized();
+  }
+
+  fn quaternion(&self) -> Quaternion {
+    self.quaternion
+  }
+
+  fn set_quaternion(&mut self, quaternion: Quaternion) {
+    self.quaternion = quaternion.normalized();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const PERIOD: f32 = 0.01;
+
+  fn norm(q: Quaternion) -> f32 {
+    sqrtf(q.w * q.w + q.x * q.x + q.y * q.y + q.z * q.z)
+  }
+
+  fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+    assert!(
+      (actual - expected).abs() <= tolerance,
+      "{actual} is not within {tolerance} of {expected}"
+    );
+  }
+
+  /// Feed a sensor resting in the orientation `euler`, starting from `IDENTITY`.
+  fn converges_at_rest(filter: &mut impl Ahrs, euler: EulerAngles) {
+    let accel = Quaternion::from_euler(euler).gravity().into();
+    for _ in 0..3000 {
+      filter.update(accel, AngularRate::ZERO);
+      assert_close(norm(filter.quaternion()), 1.0, 1e-4);
+    }
+    let estimate = filter.euler();
+    assert_close(estimate.roll, euler.roll, 0.5);
+    assert_close(estimate.pitch, euler.pitch, 0.5);
+  }
+
+  /// Rotate the sensor at `rate` for `seconds`, with the accelerometer following the rotation.
+  fn follows_rotation(filter: &mut impl Ahrs, rate: EulerAngles, seconds: f32) {
+    let steps = (seconds / PERIOD) as usize;
+    let gyro = AngularRate::new(rate.roll, rate.pitch, rate.yaw);
+    for step in 1..=steps {
+      let t = step as f32 * PERIOD;
+      let truth = Quaternion::from_euler(EulerAngles {
+        roll: rate.roll * t,
+        pitch: rate.pitch * t,
+        yaw: rate.yaw * t,
+      });
+      filter.update(truth.gravity().into(), gyro);
+      assert_close(norm(filter.quaternion()), 1.0, 1e-4);
+    }
+    let estimate = filter.euler();
+    assert_close(estimate.roll, rate.roll * seconds, 1.0);
+    assert_close(estimate.pitch, rate.pitch * seconds, 1.0);
+    assert_close(estimate.yaw, rate.yaw * seconds, 1.0);
+  }
+
+  const TILT: EulerAngles = EulerAngles {
+    roll: 30.0,
+    pitch: -20.0,
+    yaw: 0.0,
+  };
+
+  #[test]
+  fn mahony_converges_at_rest() {
+    converges_at_rest(&mut Mahony::new(PERIOD), TILT);
+  }
+
+  #[test]
+  fn madgwick_converges_at_rest() {
+    converges_at_rest(&mut Madgwick::new(PERIOD), TILT);
+  }
+
+  #[test]
+  fn mahony_follows_rotation() {
+    for rate in [(45.0, 0.0, 0.0), (0.0, 45.0, 0.0), (0.0, 0.0, 90.0)] {
+      let rate = EulerAngles {
+        roll: rate.0,
+        pitch: rate.1,
+        yaw: rate.2,
+      };
+      follows_rotation(&mut Mahony::new(PERIOD), rate, 1.0);
+    }
+  }
+
+  #[test]
+  fn madgwick_follows_rotation() {
+    for rate in [(45.0, 0.0, 0.0), (0.0, 45.0, 0.0), (0.0, 0.0, 90.0)] {
+      let rate = EulerAngles {
+        roll: rate.0,
+        pitch: rate.1,
+        yaw: rate.2,
+      };
+      follows_rotation(&mut Madgwick::new(PERIOD), rate, 1.0);
+    }
+  }
+
+  #[test]
+  fn mahony_integral_removes_gyro_bias() {
+    let mut filter = Mahony::new(PERIOD).ki(0.1);
+    let accel = Acceleration::new(0.0, 0.0, 1.0);
+    let bias = AngularRate::new(2.0, -1.0, 0.0);
+    for _ in 0..6000 {
+      filter.update(accel, bias);
+    }
+    let estimate = filter.euler();
+    assert_close(estimate.roll, 0.0, 0.1);
+    assert_close(estimate.pitch, 0.0, 0.1);
+  }
+
+  #[test]
+  fn from_accel_matches_gravity() {
+    let q = Quaternion::from_euler(TILT);
+    let estimate = Quaternion::from_accel(q.gravity().into()).euler();
+    assert_close(estimate.roll, TILT.roll, 1e-3);
+    assert_close(estimate.pitch, TILT.pitch, 1e-3);
+    assert_close(
+      norm(Quaternion::from_accel(Acceleration::new(1.0, 1.0, 0.0))),
+      1.0,
+      1e-5,
+    );
+  }
+}
@@ -1,8 +1,372 @@
-use esp_idf_hal::i2c::I2cError;
-
 use super::misc::map;
 
 const AXP192_ADDRESS: u8 = 0x34;
+const AXP192_IC_TYPE: u8 = 0x03;
+const AXP192_POWER_STATUS: u8 = 0x00;
+const AXP192_CHARGE_STATUS: u8 = 0x01;
+const AXP192_POWER_OUTPUT_CONTROL: u8 = 0x12;
+const AXP192_DCDC1_VOLTAGE: u8 = 0x26;
+const AXP192_DCDC3_VOLTAGE: u8 = 0x27;
+const AXP192_LDO23_VOLTAGE: u8 = 0x28;
+const AXP192_CHARGE_CONTROL1: u8 = 0x33;
+const AXP192_CHARGE_CONTROL2: u8 = 0x34;
+const AXP192_GPIO0_FUNCTION: u8 = 0x90;
+const AXP192_GPIO0_LDO_VOLTAGE: u8 = 0x91;
+// IRQ1 to IRQ5 enable and status registers, the status bits are cleared by writing 1.
+const AXP192_IRQ_ENABLE: [u8; 5] = [0x40, 0x41, 0x42, 0x43, 0x4A];
+const AXP192_IRQ_STATUS: [u8; 5] = [0x44, 0x45, 0x46, 0x47, 0x4D];
+const AXP192_ADC_SAMPLE_RATE: u8 = 0x84;
+const AXP192_COULOMB_CHARGE: u8 = 0xB0;
+const AXP192_COULOMB_DISCHARGE: u8 = 0xB4;
+const AXP192_COULOMB_CONTROL: u8 = 0xB8;
+
+/// Value of the IC type register of an AXP192.
+const AXP192_ID: u8 = 0x03;
+
+#[derive(Debug)]
+pub enum Error<E> {
+  /// I2C bus error
+  Bus(E),
+  /// An argument was outside of the range supported by the AXP192
+  InvalidArgument,
+  /// The IC type register did not identify an AXP192
+  UnexpectedDeviceId { found: u8 },
+  /// Data was requested from a unit that has not been enabled, e.g. the coulomb counter
+  NotInitialized,
+}
+
+impl<E> From<E> for Error<E> {
+  fn from(error: E) -> Self {
+    Error::Bus(error)
+  }
+}
+
+/// Switchable power outputs of the AXP192.
+///
+/// On the M5StickC(Plus) LDO2 feeds the LCD backlight, LDO3 the LCD logic, LDO0 (GPIO0) the
+/// microphone (StickC) or the RTC (StickC Plus), EXTEN the 5V boost for HATs and DCDC1 the ESP32
+/// itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerRail {
+  Dcdc1,
+  Dcdc3,
+  Ldo2,
+  Ldo3,
+  /// GPIO0 configured as a low noise LDO.
+  Ldo0,
+  /// External enable for the 5V boost converter. It has no adjustable voltage.
+  Exten,
+}
+
+impl PowerRail {
+  /// Returns `(min, max, step)` of the output voltage in millivolts, `None` for `Exten`.
+  pub fn voltage_range(self) -> Option<(u16, u16, u16)> {
+    match self {
+      PowerRail::Dcdc1 | PowerRail::Dcdc3 => Some((700, 3500, 25)),
+      PowerRail::Ldo2 | PowerRail::Ldo3 | PowerRail::Ldo0 => Some((1800, 3300, 100)),
+      PowerRail::Exten => None,
+    }
+  }
+
+  // Bit in the power output control register, `None` for LDO0 which is driven by the GPIO0 mux.
+  fn enable_mask(self) -> Option<u8> {
+    match self {
+      PowerRail::Dcdc1 => Some(1 << 0),
+      PowerRail::Dcdc3 => Some(1 << 1),
+      PowerRail::Ldo2 => Some(1 << 2),
+      PowerRail::Ldo3 => Some(1 << 3),
+      PowerRail::Exten => Some(1 << 6),
+      PowerRail::Ldo0 => None,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChargeVoltage {
+  V4_10 = 0,
+  V4_15 = 1,
+  V4_20 = 2,
+  V4_36 = 3,
+}
+
+impl ChargeVoltage {
+  pub fn volts(self) -> f32 {
+    match self {
+      ChargeVoltage::V4_10 => 4.10,
+      ChargeVoltage::V4_15 => 4.15,
+      ChargeVoltage::V4_20 => 4.20,
+      ChargeVoltage::V4_36 => 4.36,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChargeCurrent {
+  Ma100 = 0,
+  Ma190 = 1,
+  Ma280 = 2,
+  Ma360 = 3,
+  Ma450 = 4,
+  Ma550 = 5,
+  Ma630 = 6,
+  Ma700 = 7,
+  Ma780 = 8,
+  Ma880 = 9,
+  Ma960 = 10,
+  Ma1000 = 11,
+  Ma1080 = 12,
+  Ma1160 = 13,
+  Ma1240 = 14,
+  Ma1320 = 15,
+}
+
+impl ChargeCurrent {
+  const ALL: [ChargeCurrent; 16] = [
+    ChargeCurrent::Ma100,
+    ChargeCurrent::Ma190,
+    ChargeCurrent::Ma280,
+    ChargeCurrent::Ma360,
+    ChargeCurrent::Ma450,
+    ChargeCurrent::Ma550,
+    ChargeCurrent::Ma630,
+    ChargeCurrent::Ma700,
+    ChargeCurrent::Ma780,
+    ChargeCurrent::Ma880,
+    ChargeCurrent::Ma960,
+    ChargeCurrent::Ma1000,
+    ChargeCurrent::Ma1080,
+    ChargeCurrent::Ma1160,
+    ChargeCurrent::Ma1240,
+    ChargeCurrent::Ma1320,
+  ];
+}
+
+/// Charging stops once the current falls below this fraction of the charge current.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TerminationCurrent {
+  Percent10 = 0,
+  Percent15 = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrechargeTimeout {
+  Min30 = 0,
+  Min40 = 1,
+  Min50 = 2,
+  Min60 = 3,
+}
+
+/// Timeout of the constant current phase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConstantCurrentTimeout {
+  Hour7 = 0,
+  Hour8 = 1,
+  Hour9 = 2,
+  Hour10 = 3,
+}
+
+/// Battery charger settings.
+///
+/// The default matches what `Axp192::new` has always programmed: 4.2V at 100mA with the
+/// power-on timeouts of the chip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChargeConfig {
+  pub enabled: bool,
+  pub voltage: ChargeVoltage,
+  pub current: ChargeCurrent,
+  pub termination: TerminationCurrent,
+  pub precharge_timeout: PrechargeTimeout,
+  pub constant_current_timeout: ConstantCurrentTimeout,
+}
+
+impl Default for ChargeConfig {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      voltage: ChargeVoltage::V4_20,
+      current: ChargeCurrent::Ma100,
+      termination: TerminationCurrent::Percent10,
+      precharge_timeout: PrechargeTimeout::Min40,
+      constant_current_timeout: ConstantCurrentTimeout::Hour8,
+    }
+  }
+}
+
+/// Phase of the charger, as far as it can be told from the status registers and the battery
+/// voltage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChargeState {
+  /// No external power, no battery or charging disabled.
+  NotCharging,
+  /// Battery below 3.0V, charging with a reduced current.
+  Precharge,
+  ConstantCurrent,
+  ConstantVoltage,
+  Done,
+  /// Over-temperature, or the battery did not leave precharge before the timeout.
+  Fault,
+}
+
+/// Interrupt sources of the AXP192.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axp192Event {
+  AcinOverVoltage,
+  AcinInserted,
+  AcinRemoved,
+  VbusOverVoltage,
+  VbusInserted,
+  VbusRemoved,
+  /// VBUS dropped below V_HOLD.
+  VbusBelowHold,
+  BatteryConnected,
+  BatteryRemoved,
+  BatteryActivationStarted,
+  BatteryActivationFinished,
+  ChargeStarted,
+  ChargeFinished,
+  BatteryOverTemperature,
+  BatteryUnderTemperature,
+  /// Die temperature of the AXP192 is too high.
+  InternalOverTemperature,
+  /// Charge current is lower than the configured value.
+  ChargeCurrentLow,
+  Dcdc1UnderVoltage,
+  Dcdc2UnderVoltage,
+  Dcdc3UnderVoltage,
+  /// Short press of the power key.
+  PekShortPress,
+  /// Long press of the power key.
+  PekLongPress,
+  NoePowerOn,
+  NoePowerOff,
+  VbusValid,
+  VbusInvalid,
+  VbusSessionAB,
+  VbusSessionEnd,
+  /// APS voltage fell below the warning level.
+  LowBatteryWarning,
+  TimerTimeout,
+}
+
+impl Axp192Event {
+  pub const ALL: [Axp192Event; 30] = [
+    Axp192Event::AcinOverVoltage,
+    Axp192Event::AcinInserted,
+    Axp192Event::AcinRemoved,
+    Axp192Event::VbusOverVoltage,
+    Axp192Event::VbusInserted,
+    Axp192Event::VbusRemoved,
+    Axp192Event::VbusBelowHold,
+    Axp192Event::BatteryConnected,
+    Axp192Event::BatteryRemoved,
+    Axp192Event::BatteryActivationStarted,
+    Axp192Event::BatteryActivationFinished,
+    Axp192Event::ChargeStarted,
+    Axp192Event::ChargeFinished,
+    Axp192Event::BatteryOverTemperature,
+    Axp192Event::BatteryUnderTemperature,
+    Axp192Event::InternalOverTemperature,
+    Axp192Event::ChargeCurrentLow,
+    Axp192Event::Dcdc1UnderVoltage,
+    Axp192Event::Dcdc2UnderVoltage,
+    Axp192Event::Dcdc3UnderVoltage,
+    Axp192Event::PekShortPress,
+    Axp192Event::PekLongPress,
+    Axp192Event::NoePowerOn,
+    Axp192Event::NoePowerOff,
+    Axp192Event::VbusValid,
+    Axp192Event::VbusInvalid,
+    Axp192Event::VbusSessionAB,
+    Axp192Event::VbusSessionEnd,
+    Axp192Event::LowBatteryWarning,
+    Axp192Event::TimerTimeout,
+  ];
+
+  // (IRQ register index, bit)
+  pub(crate) fn position(self) -> (usize, u8) {
+    match self {
+      Axp192Event::AcinOverVoltage => (0, 7),
+      Axp192Event::AcinInserted => (0, 6),
+      Axp192Event::AcinRemoved => (0, 5),
+      Axp192Event::VbusOverVoltage => (0, 4),
+      Axp192Event::VbusInserted => (0, 3),
+      Axp192Event::VbusRemoved => (0, 2),
+      Axp192Event::VbusBelowHold => (0, 1),
+      Axp192Event::BatteryConnected => (1, 7),
+      Axp192Event::BatteryRemoved => (1, 6),
+      Axp192Event::BatteryActivationStarted => (1, 5),
+      Axp192Event::BatteryActivationFinished => (1, 4),
+      Axp192Event::ChargeStarted => (1, 3),
+      Axp192Event::ChargeFinished => (1, 2),
+      Axp192Event::BatteryOverTemperature => (1, 1),
+      Axp192Event::BatteryUnderTemperature => (1, 0),
+      Axp192Event::InternalOverTemperature => (2, 7),
+      Axp192Event::ChargeCurrentLow => (2, 6),
+      Axp192Event::Dcdc1UnderVoltage => (2, 5),
+      Axp192Event::Dcdc2UnderVoltage => (2, 4),
+      Axp192Event::Dcdc3UnderVoltage => (2, 3),
+      Axp192Event::PekShortPress => (2, 1),
+      Axp192Event::PekLongPress => (2, 0),
+      Axp192Event::NoePowerOn => (3, 7),
+      Axp192Event::NoePowerOff => (3, 6),
+      Axp192Event::VbusValid => (3, 5),
+      Axp192Event::VbusInvalid => (3, 4),
+      Axp192Event::VbusSessionAB => (3, 3),
+      Axp192Event::VbusSessionEnd => (3, 2),
+      Axp192Event::LowBatteryWarning => (3, 0),
+      Axp192Event::TimerTimeout => (4, 7),
+    }
+  }
+}
+
+/// A set of `Axp192Event`s, laid out like the IRQ1 to IRQ5 registers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Axp192Events([u8; 5]);
+
+impl Axp192Events {
+  pub const fn empty() -> Self {
+    Self([0; 5])
+  }
+
+  pub fn all() -> Self {
+    Axp192Event::ALL.into_iter().collect()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.iter().all(|&reg| reg == 0)
+  }
+
+  pub fn contains(&self, event: Axp192Event) -> bool {
+    let (index, bit) = event.position();
+    self.0[index] & (1 << bit) != 0
+  }
+
+  pub fn insert(&mut self, event: Axp192Event) {
+    let (index, bit) = event.position();
+    self.0[index] |= 1 << bit;
+  }
+
+  pub fn remove(&mut self, event: Axp192Event) {
+    let (index, bit) = event.position();
+    self.0[index] &= !(1 << bit);
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = Axp192Event> + '_ {
+    Axp192Event::ALL
+      .into_iter()
+      .filter(|&event| self.contains(event))
+  }
+}
+
+impl FromIterator<Axp192Event> for Axp192Events {
+  fn from_iter<T: IntoIterator<Item = Axp192Event>>(iter: T) -> Self {
+    let mut events = Self::empty();
+    for event in iter {
+      events.insert(event);
+    }
+    events
+  }
+}
 
 pub struct Axp192<I2C> {
   i2c: I2C,
@@ -11,13 +375,13 @@ pub struct Axp192<I2C> {
 impl<I2C> Axp192<I2C>
 where
   I2C: embedded_hal::i2c::I2c,
-  I2cError: From<<I2C as embedded_hal::i2c::ErrorType>::Error>,
 {
-  pub fn new(i2c: I2C) -> anyhow::Result<Self, esp_idf_hal::i2c::I2cError> {
+  pub fn new(i2c: I2C) -> Result<Self, Error<I2C::Error>> {
     let mut ret = Self { i2c };
 
     // Set LDO2 & LDO3(TFT_LED & TFT) 3.0V
-    ret.write(&[0x28, 0xcc])?;
+    ret.set_rail_voltage(PowerRail::Ldo2, 3000)?;
+    ret.set_rail_voltage(PowerRail::Ldo3, 3000)?;
 
     // // Set ADC sample rate to 200hz
     // ret.write(&[0x84, 0b11110010])?;
@@ -26,25 +390,31 @@ where
     ret.write(&[0x82, 0xff])?;
 
     // Bat charge voltage to 4.2, Current 100MA
-    ret.write(&[0x33, 0xc0])?;
+    ret.set_charge_config(&ChargeConfig::default())?;
 
     // Enable Ext, LDO2, LDO3, DCDC1
-    let buf: u8 = ret.read8bit(0x12)?;
-    ret.write(&[0x12, buf | 0x4D])?;
+    for rail in [
+      PowerRail::Exten,
+      PowerRail::Ldo2,
+      PowerRail::Ldo3,
+      PowerRail::Dcdc1,
+    ] {
+      ret.set_rail_enabled(rail, true)?;
+    }
 
     // 128ms power on, 4s power off
     ret.write(&[0x36, 0x0C])?;
 
     if cfg!(feature = "m5stickc_plus") {
       // Set RTC voltage to 3.3V
-      ret.write(&[0x91, 0xF0])?;
+      ret.set_rail_voltage(PowerRail::Ldo0, 3300)?;
     } else {
       // Set MIC voltage to 2.8V
-      ret.write(&[0x91, 0xA0])?;
+      ret.set_rail_voltage(PowerRail::Ldo0, 2800)?;
     };
 
     // Set GPIO0 to LDO
-    ret.write(&[0x90, 0x02])?;
+    ret.set_rail_enabled(PowerRail::Ldo0, true)?;
 
     // Disable vbus hold limit
     ret.write(&[0x30, 0x80])?;
@@ -67,11 +437,20 @@ where
     Ok(ret)
   }
 
-  pub fn screen_breath(&mut self, brightness: i16) -> Result<(), esp_idf_hal::i2c::I2cError> {
+  /// Check that the IC type register identifies an AXP192.
+  ///
+  /// `new` does not do this, as other IC revisions fitted to some boards report different values.
+  pub fn check_device_id(&mut self) -> Result<(), Error<I2C::Error>> {
+    let id = self.read8bit(AXP192_IC_TYPE)?;
+    if id != AXP192_ID {
+      return Err(Error::UnexpectedDeviceId { found: id });
+    }
+    Ok(())
+  }
+
+  pub fn screen_breath(&mut self, brightness: i16) -> Result<(), Error<I2C::Error>> {
     if !(0..=100).contains(&brightness) {
-      return Err(esp_idf_hal::i2c::I2cError::other(
-        esp_idf_sys::EspError::from_infallible::<{ esp_idf_sys::ESP_ERR_INVALID_ARG }>(),
-      ));
+      return Err(Error::InvalidArgument);
     }
     let vol = map(brightness.into(), 0, 100, 2500, 3200);
     let vol = if vol < 1800 { 0 } else { (vol - 1800) / 100 };
@@ -83,7 +462,96 @@ where
     Ok(())
   }
 
-  pub fn set_sleep(&mut self) -> Result<(), esp_idf_hal::i2c::I2cError> {
+  /// Switch a power rail on or off.
+  ///
+  /// Turning off `PowerRail::Dcdc1` cuts the supply of the ESP32 itself.
+  pub fn set_rail_enabled(
+    &mut self,
+    rail: PowerRail,
+    enabled: bool,
+  ) -> Result<(), Error<I2C::Error>> {
+    match rail.enable_mask() {
+      Some(mask) => {
+        let buf = self.read8bit(AXP192_POWER_OUTPUT_CONTROL)?;
+        let buf = if enabled { buf | mask } else { buf & !mask };
+        self.write(&[AXP192_POWER_OUTPUT_CONTROL, buf])
+      }
+      None => {
+        // GPIO0 function: 0b010 low noise LDO, 0b111 floating
+        let buf = self.read8bit(AXP192_GPIO0_FUNCTION)?;
+        let function = if enabled { 0x02 } else { 0x07 };
+        self.write(&[AXP192_GPIO0_FUNCTION, (buf & 0xf8) | function])
+      }
+    }
+  }
+
+  pub fn is_rail_enabled(&mut self, rail: PowerRail) -> Result<bool, Error<I2C::Error>> {
+    match rail.enable_mask() {
+      Some(mask) => Ok(self.read8bit(AXP192_POWER_OUTPUT_CONTROL)? & mask != 0),
+      None => Ok(self.read8bit(AXP192_GPIO0_FUNCTION)? & 0x07 == 0x02),
+    }
+  }
+
+  /// Set the output voltage of a rail in millivolts.
+  ///
+  /// The value must lie within `PowerRail::voltage_range` and is rounded down to the step size.
+  pub fn set_rail_voltage(
+    &mut self,
+    rail: PowerRail,
+    millivolts: u16,
+  ) -> Result<(), Error<I2C::Error>> {
+    let (min, max, step) = rail.voltage_range().ok_or(Error::InvalidArgument)?;
+    if !(min..=max).contains(&millivolts) {
+      return Err(Error::InvalidArgument);
+    }
+    let code = ((millivolts - min) / step) as u8;
+
+    match rail {
+      PowerRail::Dcdc1 | PowerRail::Dcdc3 => {
+        let addr = Self::dcdc_voltage_register(rail);
+        let buf = self.read8bit(addr)?;
+        self.write(&[addr, (buf & 0x80) | code])
+      }
+      PowerRail::Ldo2 => {
+        let buf = self.read8bit(AXP192_LDO23_VOLTAGE)?;
+        self.write(&[AXP192_LDO23_VOLTAGE, (buf & 0x0f) | (code << 4)])
+      }
+      PowerRail::Ldo3 => {
+        let buf = self.read8bit(AXP192_LDO23_VOLTAGE)?;
+        self.write(&[AXP192_LDO23_VOLTAGE, (buf & 0xf0) | code])
+      }
+      PowerRail::Ldo0 => {
+        let buf = self.read8bit(AXP192_GPIO0_LDO_VOLTAGE)?;
+        self.write(&[AXP192_GPIO0_LDO_VOLTAGE, (buf & 0x0f) | (code << 4)])
+      }
+      PowerRail::Exten => unreachable!(),
+    }
+  }
+
+  /// Read back the configured output voltage of a rail in millivolts.
+  pub fn get_rail_voltage(&mut self, rail: PowerRail) -> Result<u16, Error<I2C::Error>> {
+    let (min, _, step) = rail.voltage_range().ok_or(Error::InvalidArgument)?;
+    let code = match rail {
+      PowerRail::Dcdc1 | PowerRail::Dcdc3 => {
+        self.read8bit(Self::dcdc_voltage_register(rail))? & 0x7f
+      }
+      PowerRail::Ldo2 => self.read8bit(AXP192_LDO23_VOLTAGE)? >> 4,
+      PowerRail::Ldo3 => self.read8bit(AXP192_LDO23_VOLTAGE)? & 0x0f,
+      PowerRail::Ldo0 => self.read8bit(AXP192_GPIO0_LDO_VOLTAGE)? >> 4,
+      PowerRail::Exten => unreachable!(),
+    };
+    Ok(min + (code as u16) * step)
+  }
+
+  fn dcdc_voltage_register(rail: PowerRail) -> u8 {
+    if rail == PowerRail::Dcdc1 {
+      AXP192_DCDC1_VOLTAGE
+    } else {
+      AXP192_DCDC3_VOLTAGE
+    }
+  }
+
+  pub fn set_sleep(&mut self) -> Result<(), Error<I2C::Error>> {
     let buf = self.read8bit(0x31)?;
     self.write(&[0x31, buf | (1 << 3)])?; // Turn on short press to wake up
 
@@ -103,7 +571,10 @@ where
   }
 
   // 0 not press, 0x01 long press, 0x02 press
-  pub fn get_btn_press(&mut self) -> Result<u8, esp_idf_hal::i2c::I2cError> {
+  // Clears the press IRQs, which `drain_events` would report as `PekLongPress` and `PekShortPress`.
+  // Presses taken by `drain_events`, e.g. through `M5::update_power` for `M5::btn_pwr`, are not
+  // seen here.
+  pub fn get_btn_press(&mut self) -> Result<u8, Error<I2C::Error>> {
     let state = self.read8bit(0x46)?;
     if state > 0 {
       let _ = self.write(&[0x46, 0x03]);
@@ -111,60 +582,252 @@ where
     Ok(state)
   }
 
+  pub fn set_irq_enabled(
+    &mut self,
+    event: Axp192Event,
+    enabled: bool,
+  ) -> Result<(), Error<I2C::Error>> {
+    let (index, bit) = event.position();
+    let addr = AXP192_IRQ_ENABLE[index];
+    let buf = self.read8bit(addr)?;
+    let buf = if enabled {
+      buf | (1 << bit)
+    } else {
+      buf & !(1 << bit)
+    };
+    self.write(&[addr, buf])
+  }
+
+  /// Enable exactly the given IRQs and mask all others.
+  pub fn set_enabled_irqs(&mut self, events: Axp192Events) -> Result<(), Error<I2C::Error>> {
+    for (addr, bits) in AXP192_IRQ_ENABLE.into_iter().zip(events.0) {
+      self.write(&[addr, bits])?;
+    }
+    Ok(())
+  }
+
+  pub fn get_enabled_irqs(&mut self) -> Result<Axp192Events, Error<I2C::Error>> {
+    let mut events = Axp192Events::empty();
+    for (bits, addr) in events.0.iter_mut().zip(AXP192_IRQ_ENABLE) {
+      *bits = self.read8bit(addr)?;
+    }
+    Ok(events)
+  }
+
+  /// Read the IRQ status registers without clearing them.
+  pub fn get_pending_events(&mut self) -> Result<Axp192Events, Error<I2C::Error>> {
+    let mut events = Axp192Events::empty();
+    for (bits, addr) in events.0.iter_mut().zip(AXP192_IRQ_STATUS) {
+      *bits = self.read8bit(addr)?;
+    }
+    Ok(events)
+  }
+
+  pub fn clear_events(&mut self, events: Axp192Events) -> Result<(), Error<I2C::Error>> {
+    for (addr, bits) in AXP192_IRQ_STATUS.into_iter().zip(events.0) {
+      if bits != 0 {
+        self.write(&[addr, bits])?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Read and clear all pending events.
+  pub fn drain_events(&mut self) -> Result<Axp192Events, Error<I2C::Error>> {
+    let events = self.get_pending_events()?;
+    self.clear_events(events)?;
+    Ok(events)
+  }
+
+  pub fn set_charge_config(&mut self, config: &ChargeConfig) -> Result<(), Error<I2C::Error>> {
+    let control1 = ((config.enabled as u8) << 7)
+      | ((config.voltage as u8) << 5)
+      | ((config.termination as u8) << 4)
+      | (config.current as u8);
+    self.write(&[AXP192_CHARGE_CONTROL1, control1])?;
+
+    // Bits 5-2 configure the external path and are kept
+    let buf = self.read8bit(AXP192_CHARGE_CONTROL2)?;
+    let control2 = (buf & 0x3c)
+      | ((config.precharge_timeout as u8) << 6)
+      | (config.constant_current_timeout as u8);
+    self.write(&[AXP192_CHARGE_CONTROL2, control2])
+  }
+
+  pub fn get_charge_config(&mut self) -> Result<ChargeConfig, Error<I2C::Error>> {
+    let control1 = self.read8bit(AXP192_CHARGE_CONTROL1)?;
+    let control2 = self.read8bit(AXP192_CHARGE_CONTROL2)?;
+
+    Ok(ChargeConfig {
+      enabled: control1 & 0x80 != 0,
+      voltage: match (control1 >> 5) & 0x03 {
+        0 => ChargeVoltage::V4_10,
+        1 => ChargeVoltage::V4_15,
+        2 => ChargeVoltage::V4_20,
+        _ => ChargeVoltage::V4_36,
+      },
+      current: ChargeCurrent::ALL[(control1 & 0x0f) as usize],
+      termination: if control1 & 0x10 != 0 {
+        TerminationCurrent::Percent15
+      } else {
+        TerminationCurrent::Percent10
+      },
+      precharge_timeout: match control2 >> 6 {
+        0 => PrechargeTimeout::Min30,
+        1 => PrechargeTimeout::Min40,
+        2 => PrechargeTimeout::Min50,
+        _ => PrechargeTimeout::Min60,
+      },
+      constant_current_timeout: match control2 & 0x03 {
+        0 => ConstantCurrentTimeout::Hour7,
+        1 => ConstantCurrentTimeout::Hour8,
+        2 => ConstantCurrentTimeout::Hour9,
+        _ => ConstantCurrentTimeout::Hour10,
+      },
+    })
+  }
+
+  pub fn get_charge_state(&mut self) -> Result<ChargeState, Error<I2C::Error>> {
+    let power = self.read8bit(AXP192_POWER_STATUS)?;
+    let charge = self.read8bit(AXP192_CHARGE_STATUS)?;
+
+    let over_temperature = charge & 0x80 != 0;
+    let charging = charge & 0x40 != 0;
+    let battery_present = charge & 0x20 != 0;
+    let activation_mode = charge & 0x08 != 0;
+    let external_power = power & 0xa0 != 0; // ACIN or VBUS
+
+    if over_temperature || activation_mode {
+      return Ok(ChargeState::Fault);
+    }
+    if !battery_present || !external_power {
+      return Ok(ChargeState::NotCharging);
+    }
+
+    let config = self.get_charge_config()?;
+    if !config.enabled {
+      return Ok(ChargeState::NotCharging);
+    }
+    if !charging {
+      return Ok(ChargeState::Done);
+    }
+
+    let voltage = self.get_bat_voltage()?;
+    Ok(if voltage < 3.0 {
+      ChargeState::Precharge
+    } else if voltage >= config.voltage.volts() - 0.05 {
+      ChargeState::ConstantVoltage
+    } else {
+      ChargeState::ConstantCurrent
+    })
+  }
+
   /// Get whether the battery is currently charging or not.
-  pub fn is_charging(&mut self) -> anyhow::Result<bool, esp_idf_hal::i2c::I2cError> {
+  pub fn is_charging(&mut self) -> Result<bool, Error<I2C::Error>> {
     Ok((self.read8bit(0x00)? & 0x04) > 0)
   }
 
-  pub fn get_bat_voltage(&mut self) -> anyhow::Result<f32, esp_idf_hal::i2c::I2cError> {
+  pub fn get_bat_voltage(&mut self) -> Result<f32, Error<I2C::Error>> {
     const ADCLSB: f32 = 1.1 / 1000.0;
     let data = self.read12bit(0x78)? as f32;
     Ok(data * ADCLSB)
   }
-  pub fn get_bat_current(&mut self) -> anyhow::Result<f32, esp_idf_hal::i2c::I2cError> {
+  pub fn get_bat_current(&mut self) -> Result<f32, Error<I2C::Error>> {
     const ADCLSB: f32 = 0.5;
     let current_in = self.read13bit(0x7A)? as f32;
     let current_out = self.read13bit(0x7C)? as f32;
     Ok((current_in - current_out) * ADCLSB)
   }
 
-  pub fn get_vbus_voltage(&mut self) -> anyhow::Result<f32, esp_idf_hal::i2c::I2cError> {
+  pub fn get_vbus_voltage(&mut self) -> Result<f32, Error<I2C::Error>> {
     const ADCLSB: f32 = 1.7 / 1000.0;
     let data = self.read12bit(0x5A)? as f32;
     Ok(data * ADCLSB)
   }
 
-  pub fn get_vbus_current(&mut self) -> anyhow::Result<f32, esp_idf_hal::i2c::I2cError> {
+  pub fn get_vbus_current(&mut self) -> Result<f32, Error<I2C::Error>> {
     const ADCLSB: f32 = 0.375;
     let data = self.read12bit(0x5C)? as f32;
     Ok(data * ADCLSB)
   }
 
-  pub fn get_temp_in_axp192(&mut self) -> anyhow::Result<f32, esp_idf_hal::i2c::I2cError> {
+  pub fn get_temp_in_axp192(&mut self) -> Result<f32, Error<I2C::Error>> {
     const ADCLSB: f32 = 0.1;
     const OFFSET_DEG_C: f32 = -144.7;
     let data = self.read12bit(0x5E)?;
     Ok(OFFSET_DEG_C + (data as f32) * ADCLSB)
   }
 
-  fn write(&mut self, bytes: &[u8]) -> anyhow::Result<(), esp_idf_hal::i2c::I2cError> {
+  /// ADC sample rate in Hz (25, 50, 100 or 200).
+  pub fn get_adc_sample_rate(&mut self) -> Result<u16, Error<I2C::Error>> {
+    let buf = self.read8bit(AXP192_ADC_SAMPLE_RATE)?;
+    Ok(25 << (buf >> 6))
+  }
+
+  pub fn enable_coulomb_counter(&mut self) -> Result<(), Error<I2C::Error>> {
+    self.write(&[AXP192_COULOMB_CONTROL, 0x80])
+  }
+
+  pub fn disable_coulomb_counter(&mut self) -> Result<(), Error<I2C::Error>> {
+    self.write(&[AXP192_COULOMB_CONTROL, 0x00])
+  }
+
+  /// Stop counting while keeping the accumulated values. `enable_coulomb_counter` resumes.
+  pub fn pause_coulomb_counter(&mut self) -> Result<(), Error<I2C::Error>> {
+    self.write(&[AXP192_COULOMB_CONTROL, 0xC0])
+  }
+
+  /// Reset both counters to zero and keep counting.
+  pub fn clear_coulomb_counter(&mut self) -> Result<(), Error<I2C::Error>> {
+    self.write(&[AXP192_COULOMB_CONTROL, 0xA0])
+  }
+
+  pub fn get_coulomb_charge_data(&mut self) -> Result<u32, Error<I2C::Error>> {
+    self.read32bit(AXP192_COULOMB_CHARGE)
+  }
+
+  pub fn get_coulomb_discharge_data(&mut self) -> Result<u32, Error<I2C::Error>> {
+    self.read32bit(AXP192_COULOMB_DISCHARGE)
+  }
+
+  /// Net charge that went into the battery since the counter was cleared, in mAh.
+  ///
+  /// Fails with `NotInitialized` unless the counter has been enabled with `enable_coulomb_counter`.
+  pub fn get_coulomb_data(&mut self) -> Result<f32, Error<I2C::Error>> {
+    if self.read8bit(AXP192_COULOMB_CONTROL)? & 0x80 == 0 {
+      return Err(Error::NotInitialized);
+    }
+    let charge = self.get_coulomb_charge_data()? as i64;
+    let discharge = self.get_coulomb_discharge_data()? as i64;
+    let rate = self.get_adc_sample_rate()? as f32;
+    // 65536 * 0.5mA per count, accumulated once per ADC sample
+    Ok(65536.0 * 0.5 * ((charge - discharge) as f32) / 3600.0 / rate)
+  }
+
+  fn write(&mut self, bytes: &[u8]) -> Result<(), Error<I2C::Error>> {
     self.i2c.write(AXP192_ADDRESS, bytes)?;
     Ok(())
   }
 
-  fn read8bit(&mut self, addr: u8) -> anyhow::Result<u8, esp_idf_hal::i2c::I2cError> {
+  fn read8bit(&mut self, addr: u8) -> Result<u8, Error<I2C::Error>> {
     let mut buf = [0x00u8];
     self.i2c.write_read(AXP192_ADDRESS, &[addr], &mut buf)?;
     Ok(buf[0])
   }
 
-  fn read12bit(&mut self, addr: u8) -> anyhow::Result<u16, esp_idf_hal::i2c::I2cError> {
+  fn read32bit(&mut self, addr: u8) -> Result<u32, Error<I2C::Error>> {
+    let mut buf = [0x00u8; 4];
+    self.i2c.write_read(AXP192_ADDRESS, &[addr], &mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+  }
+
+  fn read12bit(&mut self, addr: u8) -> Result<u16, Error<I2C::Error>> {
     let mut buf = [0x00u8; 2];
     self.i2c.write_read(AXP192_ADDRESS, &[addr], &mut buf)?;
     Ok(((buf[0] as u16) << 4) + (buf[1] as u16))
   }
 
-  fn read13bit(&mut self, addr: u8) -> anyhow::Result<u16, esp_idf_hal::i2c::I2cError> {
+  fn read13bit(&mut self, addr: u8) -> Result<u16, Error<I2C::Error>> {
     let mut buf = [0x00u8; 2];
     self.i2c.write_read(AXP192_ADDRESS, &[addr], &mut buf)?;
     Ok(((buf[0] as u16) << 5) + (buf[1] as u16))
@@ -0,0 +1,325 @@
+use super::axp192::{self, Axp192};
+
+// Resting open-circuit voltage of a single LiPo cell against state of charge.
+const LIPO_OCV_CURVE: [(f32, f32); 21] = [
+  (3.27, 0.0),
+  (3.61, 5.0),
+  (3.69, 10.0),
+  (3.71, 15.0),
+  (3.73, 20.0),
+  (3.75, 25.0),
+  (3.77, 30.0),
+  (3.79, 35.0),
+  (3.80, 40.0),
+  (3.82, 45.0),
+  (3.84, 50.0),
+  (3.85, 55.0),
+  (3.87, 60.0),
+  (3.91, 65.0),
+  (3.95, 70.0),
+  (3.98, 75.0),
+  (4.02, 80.0),
+  (4.08, 85.0),
+  (4.11, 90.0),
+  (4.15, 95.0),
+  (4.20, 100.0),
+];
+
+/// Estimate the state of charge in percent from a resting battery voltage.
+pub fn percentage_from_ocv(voltage: f32) -> f32 {
+  let (first_voltage, _) = LIPO_OCV_CURVE[0];
+  if voltage <= first_voltage {
+    return 0.0;
+  }
+  for window in LIPO_OCV_CURVE.windows(2) {
+    let (v0, p0) = window[0];
+    let (v1, p1) = window[1];
+    if voltage <= v1 {
+      return p0 + (voltage - v0) * (p1 - p0) / (v1 - v0);
+    }
+  }
+  100.0
+}
+
+/// One set of measurements the gauge works on.
+#[derive(Clone, Copy, Debug)]
+pub struct BatteryReading {
+  /// Battery voltage in V.
+  pub voltage: f32,
+  /// Battery current in mA, positive while charging.
+  pub current: f32,
+  /// Coulomb counter in mAh, see `Axp192::get_coulomb_data`.
+  pub coulomb: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BatteryStatus {
+  pub remaining_mah: f32,
+  /// State of charge, 0 to 100.
+  pub percentage: f32,
+  /// Minutes until empty at the current discharge rate.
+  pub time_to_empty: Option<f32>,
+  /// Minutes until full at the current charge rate.
+  pub time_to_full: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+  /// The capacity is not a positive, finite number of mAh
+  InvalidCapacity,
+}
+
+/// Battery gauge built on the AXP192 coulomb counter.
+///
+/// The counter tracks charge going in and out of the battery; whenever the current is close to zero
+/// the battery voltage approximates the open-circuit voltage and the estimate is pulled towards the
+/// LiPo curve to cancel accumulated counter drift. The coulomb counter must be enabled with
+/// `Axp192::enable_coulomb_counter`.
+pub struct BatteryGauge {
+  capacity_mah: f32,
+  rest_current: f32,
+  resync_gain: f32,
+  // Remaining charge at the moment the counter read `coulomb_base`.
+  remaining_base: Option<f32>,
+  coulomb_base: f32,
+}
+
+impl BatteryGauge {
+  /// Built-in cell: 95mAh on the M5StickC, 120mAh on the M5StickC Plus.
+  pub const DEFAULT_CAPACITY_MAH: f32 = if cfg!(feature = "m5stickc_plus") {
+    120.0
+  } else {
+    95.0
+  };
+
+  pub fn new(capacity_mah: f32) -> Result<Self, Error> {
+    if !(capacity_mah.is_finite() && capacity_mah > 0.0) {
+      return Err(Error::InvalidCapacity);
+    }
+    Ok(Self::with_capacity(capacity_mah))
+  }
+
+  fn with_capacity(capacity_mah: f32) -> Self {
+    Self {
+      capacity_mah,
+      rest_current: 5.0,
+      resync_gain: 0.1,
+      remaining_base: None,
+      coulomb_base: 0.0,
+    }
+  }
+
+  /// Below this absolute current in mA the battery is considered at rest and the voltage is used
+  /// to correct the estimate.
+  pub fn with_rest_current(mut self, current: f32) -> Self {
+    self.rest_current = current;
+    self
+  }
+
+  /// Fraction of the difference to the open-circuit estimate applied per resting update.
+  pub fn with_resync_gain(mut self, gain: f32) -> Self {
+    self.resync_gain = gain.clamp(0.0, 1.0);
+    self
+  }
+
+  pub fn capacity_mah(&self) -> f32 {
+    self.capacity_mah
+  }
+
+  /// Forget the current estimate, the next update starts again from the voltage curve.
+  pub fn reset(&mut self) {
+    self.remaining_base = None;
+  }
+
+  pub fn update(&mut self, reading: BatteryReading) -> BatteryStatus {
+    let ocv_remaining = percentage_from_ocv(reading.voltage) / 100.0 * self.capacity_mah;
+    let at_rest = reading.current.abs() < self.rest_current;
+
+    let remaining = match self.remaining_base {
+      None => ocv_remaining,
+      Some(base) => {
+        let counted = base + (reading.coulomb - self.coulomb_base);
+        if at_rest {
+          counted + (ocv_remaining - counted) * self.resync_gain
+        } else {
+          counted
+        }
+      }
+    };
+    let remaining = remaining.clamp(0.0, self.capacity_mah);
+    self.remaining_base = Some(remaining);
+    self.coulomb_base = reading.coulomb;
+
+    let (time_to_empty, time_to_full) = if at_rest {
+      (None, None)
+    } else if reading.current < 0.0 {
+      (Some(remaining / -reading.current * 60.0), None)
+    } else {
+      (
+        None,
+        Some((self.capacity_mah - remaining) / reading.current * 60.0),
+      )
+    };
+
+    BatteryStatus {
+      remaining_mah: remaining,
+      percentage: remaining / self.capacity_mah * 100.0,
+      time_to_empty,
+      time_to_full,
+    }
+  }
+
+  /// Read voltage, current and coulomb counter from the AXP192 and update the estimate.
+  pub fn update_from<I2C>(
+    &mut self,
+    axp: &mut Axp192<I2C>,
+  ) -> Result<BatteryStatus, axp192::Error<I2C::Error>>
+  where
+    I2C: embedded_hal::i2c::I2c,
+  {
+    let reading = BatteryReading {
+      voltage: axp.get_bat_voltage()?,
+      current: axp.get_bat_current()?,
+      coulomb: axp.get_coulomb_data()?,
+    };
+    Ok(self.update(reading))
+  }
+}
+
+impl Default for BatteryGauge {
+  fn default() -> Self {
+    Self::with_capacity(Self::DEFAULT_CAPACITY_MAH)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rejects_non_positive_capacity() {
+    assert_eq!(BatteryGauge::new(0.0).err(), Some(Error::InvalidCapacity));
+    assert_eq!(BatteryGauge::new(-95.0).err(), Some(Error::InvalidCapacity));
+    for capacity in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+      assert_eq!(
+        BatteryGauge::new(capacity).err(),
+        Some(Error::InvalidCapacity)
+      );
+    }
+  }
+
+  #[test]
+  fn starts_from_ocv() {
+    let mut gauge = BatteryGauge::new(100.0).unwrap();
+    let status = gauge.update(BatteryReading {
+      voltage: 4.2,
+      current: -50.0,
+      coulomb: 12.0,
+    });
+    assert_eq!(status.remaining_mah, 100.0);
+    assert_eq!(percentage_from_ocv(3.0), 0.0);
+    assert_eq!(percentage_from_ocv(4.3), 100.0);
+    assert!((percentage_from_ocv(3.83) - 47.5).abs() < 0.01);
+  }
+
+  #[test]
+  fn counts_charge_while_loaded() {
+    let mut gauge = BatteryGauge::new(100.0).unwrap();
+    gauge.update(BatteryReading {
+      voltage: 3.84,
+      current: -50.0,
+      coulomb: 0.0,
+    });
+    // The voltage sags under load, only the counter is used
+    let status = gauge.update(BatteryReading {
+      voltage: 3.5,
+      current: -50.0,
+      coulomb: -10.0,
+    });
+    assert!((status.remaining_mah - 40.0).abs() < 1e-3);
+    assert!((status.time_to_empty.unwrap() - 48.0).abs() < 1e-3);
+    assert_eq!(status.time_to_full, None);
+
+    let status = gauge.update(BatteryReading {
+      voltage: 4.0,
+      current: 100.0,
+      coulomb: 0.0,
+    });
+    assert!((status.remaining_mah - 50.0).abs() < 1e-3);
+    assert!((status.time_to_full.unwrap() - 30.0).abs() < 1e-3);
+    assert_eq!(status.time_to_empty, None);
+  }
+
+  #[test]
+  fn resyncs_to_ocv_at_rest() {
+    let mut gauge = BatteryGauge::new(100.0)
+      .unwrap()
+      .with_resync_gain(0.5)
+      .with_rest_current(2.0);
+    gauge.update(BatteryReading {
+      voltage: 3.84,
+      current: -50.0,
+      coulomb: 0.0,
+    });
+    // At rest the estimate moves half way to the 80% of 4.02V per update
+    let status = gauge.update(BatteryReading {
+      voltage: 4.02,
+      current: 1.0,
+      coulomb: 0.0,
+    });
+    assert!((status.remaining_mah - 65.0).abs() < 1e-3);
+    let status = gauge.update(BatteryReading {
+      voltage: 4.02,
+      current: -1.0,
+      coulomb: 0.0,
+    });
+    assert!((status.remaining_mah - 72.5).abs() < 1e-3);
+    // No rate at rest
+    assert_eq!(status.time_to_empty, None);
+    assert_eq!(status.time_to_full, None);
+
+    let status = gauge.update(BatteryReading {
+      voltage: 4.02,
+      current: 0.0,
+      coulomb: 0.0,
+    });
+    assert_eq!(status.time_to_empty, None);
+    assert_eq!(status.time_to_full, None);
+
+    gauge.reset();
+    let status = gauge.update(BatteryReading {
+      voltage: 3.84,
+      current: -50.0,
+      coulomb: 0.0,
+    });
+    assert!((status.remaining_mah - 50.0).abs() < 1e-3);
+  }
+
+  #[test]
+  fn percentage_stays_in_range() {
+    let mut gauge = BatteryGauge::new(95.0).unwrap();
+    let status = gauge.update(BatteryReading {
+      voltage: 3.84,
+      current: 0.0,
+      coulomb: 0.0,
+    });
+    assert!((status.percentage - 50.0).abs() < 0.01);
+
+    let status = gauge.update(BatteryReading {
+      voltage: 3.84,
+      current: -50.0,
+      coulomb: -200.0,
+    });
+    assert_eq!(status.remaining_mah, 0.0);
+    assert_eq!(status.percentage, 0.0);
+
+    let status = gauge.update(BatteryReading {
+      voltage: 4.2,
+      current: 50.0,
+      coulomb: 500.0,
+    });
+    assert_eq!(status.remaining_mah, 95.0);
+    assert_eq!(status.percentage, 100.0);
+    assert_eq!(status.time_to_full, Some(0.0));
+  }
+}
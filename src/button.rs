@@ -1,31 +1,117 @@
-use super::misc::millis;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
 
-pub struct Button<PIN: embedded_hal::digital::InputPin> {
+use alloc::sync::Arc;
+
+use crate::axp192::{Axp192Event, Axp192Events};
+use crate::click::ButtonEvent;
+use crate::time::{Clock, Instant, SystemClock};
+
+/// Queue of pin change timestamps from an interrupt handler to a `Button`.
+///
+/// Only the time is recorded, so the handler does not have to read the pin; the button takes each
+/// change as a toggle of its state and checks the pin level itself. Lock-free with a single
+/// producer, the ISR, and a single consumer, the button. Changes arriving while the queue is full
+/// are dropped; the button then catches up from the pin level.
+pub struct EdgeQueue {
+  slots: [UnsafeCell<Instant>; Self::CAPACITY],
+  head: AtomicUsize,
+  tail: AtomicUsize,
+  overflowed: AtomicBool,
+}
+
+// `push` and `pop` never touch the same slot at the same time
+unsafe impl Sync for EdgeQueue {}
+
+impl EdgeQueue {
+  pub const CAPACITY: usize = 32;
+
+  pub fn new() -> Self {
+    Self {
+      slots: core::array::from_fn(|_| UnsafeCell::new(Instant::default())),
+      head: AtomicUsize::new(0),
+      tail: AtomicUsize::new(0),
+      overflowed: AtomicBool::new(false),
+    }
+  }
+
+  /// Add the time of a pin change. Must only be called from one context, e.g. the ISR of the pin.
+  pub fn push(&self, timestamp: Instant) {
+    let head = self.head.load(Ordering::Relaxed);
+    if head.wrapping_sub(self.tail.load(Ordering::Acquire)) == Self::CAPACITY {
+      self.overflowed.store(true, Ordering::Relaxed);
+      return;
+    }
+    unsafe { *self.slots[head % Self::CAPACITY].get() = timestamp };
+    self.head.store(head.wrapping_add(1), Ordering::Release);
+  }
+
+  /// Remove the oldest change. Must only be called from one context.
+  pub fn pop(&self) -> Option<Instant> {
+    let tail = self.tail.load(Ordering::Relaxed);
+    if tail == self.head.load(Ordering::Acquire) {
+      return None;
+    }
+    let timestamp = unsafe { *self.slots[tail % Self::CAPACITY].get() };
+    self.tail.store(tail.wrapping_add(1), Ordering::Release);
+    Some(timestamp)
+  }
+
+  /// Changes were dropped since the last call.
+  pub fn take_overflowed(&self) -> bool {
+    self.overflowed.swap(false, Ordering::Relaxed)
+  }
+}
+
+impl Default for EdgeQueue {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+pub struct Button<PIN: embedded_hal::digital::InputPin, C: Clock = SystemClock> {
   pin: PIN,
-  state: bool,      // current button state
+  clock: C,
+  edges: Option<Arc<EdgeQueue>>,
+  state: bool,          // current button state
   invert: bool, // if false, interpret high state as pressed, else interpret low state as pressed
   last_state: bool, // previous button state
   changed: bool, // state changed since last read
-  time: u32,    // time of current state (all times are in ms)
-  last_time: u32, // time of previous state
-  last_change: u32, // time of last state change
-  db_time: u32, // debounce time
-  press_time: u32, // press time
+  time: Instant, // time of current state
+  last_time: Instant, // time of previous state
+  last_change: Instant, // time of last state change
+  db_time: Duration, // debounce time
+  press_time: Instant, // press time
 }
 
 impl<PIN> Button<PIN>
 where
   PIN: embedded_hal::digital::InputPin,
 {
+  /// `db_time` is the debounce time in ms.
   pub fn new(pin: PIN, invert: bool, db_time: u32) -> Self {
+    Self::with_clock(pin, invert, db_time, SystemClock)
+  }
+}
+
+impl<PIN, C> Button<PIN, C>
+where
+  PIN: embedded_hal::digital::InputPin,
+  C: Clock,
+{
+  /// Like `new`, with the time taken from `clock`, e.g. a `ManualClock` in host tests.
+  pub fn with_clock(pin: PIN, invert: bool, db_time: u32, clock: C) -> Self {
     let mut state = pin.is_high().unwrap();
     if invert {
       state = !state;
     }
 
-    let time = millis();
+    let time = clock.now();
     Self {
       pin,
+      clock,
+      edges: None,
       state,
       invert,
       last_state: state,
@@ -33,17 +119,33 @@ where
       last_time: time,
       changed: false,
       last_change: time,
-      db_time,
+      db_time: Duration::from_millis(db_time as u64),
       press_time: time,
     }
   }
 
+  pub fn pin_mut(&mut self) -> &mut PIN {
+    &mut self.pin
+  }
+
+  /// Take the state from edges pushed into `queue`, e.g. by the pin's ISR, instead of polling the
+  /// pin. `None` goes back to polling.
+  ///
+  /// Every `read` then applies the next queued change that survives debouncing, so a press
+  /// shorter than the time between two reads still shows up in `was_pressed` and `was_released`.
+  /// Once no change is queued, the pin is read to correct the state.
+  pub fn set_edge_queue(&mut self, queue: Option<Arc<EdgeQueue>>) {
+    self.edges = queue;
+  }
+
   pub fn read(&mut self) -> bool {
-    let ms = millis();
+    let now = self.clock.now();
 
     self.last_time = self.time;
-    self.time = ms;
-    if ms - self.last_change < self.db_time {
+    self.time = now;
+    if self.edges.is_some() {
+      self.read_edges(now);
+    } else if now - self.last_change < self.db_time {
       self.changed = false;
     } else {
       self.last_state = self.state;
@@ -53,7 +155,7 @@ where
       }
       self.changed = self.state != self.last_state;
       if self.changed {
-        self.last_change = ms;
+        self.last_change = now;
         self.changed = true;
         if self.state {
           self.press_time = self.time;
@@ -63,7 +165,426 @@ where
     self.state
   }
 
+  fn read_edges(&mut self, now: Instant) {
+    self.changed = false;
+    while let Some(timestamp) = self.edges.as_ref().and_then(|edges| edges.pop()) {
+      // Edges within the debounce time, or from before a change read from the pin, are bounces
+      if timestamp < self.last_change + self.db_time {
+        continue;
+      }
+      self.time = timestamp;
+      self.set_state(!self.state, timestamp);
+      return;
+    }
+
+    // The bouncing may have ended on a level whose edge was dropped or ignored, or the button was
+    // released again before the next edge could be queued
+    if now - self.last_change >= self.db_time {
+      let state = self.pin.is_high().unwrap() != self.invert;
+      if state != self.state {
+        self.set_state(state, now);
+      }
+    }
+  }
+
+  fn set_state(&mut self, state: bool, time: Instant) {
+    self.last_state = self.state;
+    self.state = state;
+    self.changed = true;
+    self.last_change = time;
+    if state {
+      self.press_time = time;
+    }
+  }
+
   pub fn pressed(&self) -> bool {
     self.state
   }
+
+  pub fn released(&self) -> bool {
+    !self.state
+  }
+
+  /// The button went down in the last `read`.
+  pub fn was_pressed(&self) -> bool {
+    self.state && self.changed
+  }
+
+  /// The button went up in the last `read`.
+  pub fn was_released(&self) -> bool {
+    !self.state && self.changed
+  }
+
+  /// The button went up in the last `read` after being held for at least `ms`.
+  pub fn was_released_for(&self, ms: u32) -> bool {
+    !self.state && self.changed && self.time - self.press_time >= Duration::from_millis(ms as u64)
+  }
+
+  /// The button has been held for at least `ms` as of the last `read`.
+  pub fn pressed_for(&self, ms: u32) -> bool {
+    self.state && self.time - self.last_change >= Duration::from_millis(ms as u64)
+  }
+
+  /// The button has been up for at least `ms` as of the last `read`.
+  pub fn released_for(&self, ms: u32) -> bool {
+    !self.state && self.time - self.last_change >= Duration::from_millis(ms as u64)
+  }
+
+  /// Time of the last state change.
+  pub fn last_change(&self) -> Instant {
+    self.last_change
+  }
+}
+
+/// What `Button` and `PowerButton` have in common, so UI code can handle all three buttons of the
+/// M5StickC alike, e.g. through `M5::buttons`.
+pub trait PushButton {
+  /// The button is down as of the last update.
+  fn pressed(&self) -> bool;
+
+  /// The button went down in the last update.
+  fn was_pressed(&self) -> bool;
+
+  /// The button went up in the last update.
+  fn was_released(&self) -> bool;
+
+  /// Time of the last state change.
+  fn last_change(&self) -> Instant;
+}
+
+impl<PIN, C> PushButton for Button<PIN, C>
+where
+  PIN: embedded_hal::digital::InputPin,
+  C: Clock,
+{
+  fn pressed(&self) -> bool {
+    Button::pressed(self)
+  }
+
+  fn was_pressed(&self) -> bool {
+    Button::was_pressed(self)
+  }
+
+  fn was_released(&self) -> bool {
+    Button::was_released(self)
+  }
+
+  fn last_change(&self) -> Instant {
+    Button::last_change(self)
+  }
+}
+
+/// The power key of the AXP192, read through its press IRQs.
+///
+/// The AXP192 does not report the level of the key, only that a short press ended or that the key
+/// has been held for the long press time. Either is reported as a press in the update that sees
+/// the IRQ and a release in the next one, so the key behaves like a `Button` that is held for
+/// one update.
+///
+/// The press IRQs are taken from `Axp192::drain_events`. Anything else that clears them, e.g.
+/// `Axp192::get_btn_press`, takes the presses away from this button.
+#[derive(Clone, Debug)]
+pub struct PowerButton<C: Clock = SystemClock> {
+  clock: C,
+  event: Option<ButtonEvent>,
+  pending: Option<ButtonEvent>,
+  state: bool,
+  changed: bool,
+  last_change: Instant,
+}
+
+impl PowerButton {
+  pub fn new() -> Self {
+    Self::with_clock(SystemClock)
+  }
+}
+
+impl Default for PowerButton {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<C: Clock> PowerButton<C> {
+  pub fn with_clock(clock: C) -> Self {
+    Self {
+      clock,
+      event: None,
+      pending: None,
+      state: false,
+      changed: false,
+      last_change: Instant::default(),
+    }
+  }
+
+  /// Process the events returned by `Axp192::drain_events`, ignoring all but the press IRQs.
+  pub fn read(&mut self, events: &Axp192Events) -> Option<ButtonEvent> {
+    self.update(self.clock.now(), events)
+  }
+
+  /// Like `read`, with the events seen at `timestamp`.
+  pub fn update(&mut self, timestamp: Instant, events: &Axp192Events) -> Option<ButtonEvent> {
+    if events.contains(Axp192Event::PekLongPress) {
+      self.pending = Some(ButtonEvent::LongPress);
+    } else if events.contains(Axp192Event::PekShortPress) {
+      self.pending = Some(ButtonEvent::Click);
+    }
+
+    // A press arriving while the previous one is still reported waits for its release
+    self.changed = self.state || self.pending.is_some();
+    self.event = if self.state {
+      None
+    } else {
+      self.pending.take()
+    };
+    self.state = self.event.is_some();
+    if self.changed {
+      self.last_change = timestamp;
+    }
+    self.event
+  }
+
+  /// `Click` for a short press and `LongPress` for a long one, set together with `was_pressed`.
+  pub fn event(&self) -> Option<ButtonEvent> {
+    self.event
+  }
+
+  pub fn pressed(&self) -> bool {
+    self.state
+  }
+
+  /// A short or long press was reported in the last `read`.
+  pub fn was_pressed(&self) -> bool {
+    self.state && self.changed
+  }
+
+  /// The update after a press.
+  pub fn was_released(&self) -> bool {
+    !self.state && self.changed
+  }
+
+  pub fn was_short_pressed(&self) -> bool {
+    self.event == Some(ButtonEvent::Click)
+  }
+
+  pub fn was_long_pressed(&self) -> bool {
+    self.event == Some(ButtonEvent::LongPress)
+  }
+
+  /// Time of the last state change.
+  pub fn last_change(&self) -> Instant {
+    self.last_change
+  }
+}
+
+impl<C: Clock> PushButton for PowerButton<C> {
+  fn pressed(&self) -> bool {
+    PowerButton::pressed(self)
+  }
+
+  fn was_pressed(&self) -> bool {
+    PowerButton::was_pressed(self)
+  }
+
+  fn was_released(&self) -> bool {
+    PowerButton::was_released(self)
+  }
+
+  fn last_change(&self) -> Instant {
+    PowerButton::last_change(self)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::time::ManualClock;
+  use alloc::rc::Rc;
+  use core::cell::Cell;
+
+  /// Input pin whose level the test sets.
+  struct Pin(Rc<Cell<bool>>);
+
+  impl embedded_hal::digital::ErrorType for Pin {
+    type Error = core::convert::Infallible;
+  }
+
+  impl embedded_hal::digital::InputPin for Pin {
+    fn is_high(&self) -> Result<bool, Self::Error> {
+      Ok(self.0.get())
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+      Ok(!self.0.get())
+    }
+  }
+
+  /// An active low button, released, with a clock just before the millisecond count passes
+  /// `u32::MAX`.
+  fn button() -> (Button<Pin, ManualClock>, Rc<Cell<bool>>, ManualClock) {
+    let level = Rc::new(Cell::new(true));
+    let clock = ManualClock::new(Instant::from_millis(u32::MAX as u64 - 50));
+    let button = Button::with_clock(Pin(level.clone()), true, 10, clock.clone());
+    (button, level, clock)
+  }
+
+  #[test]
+  fn debounce() {
+    let (mut button, level, clock) = button();
+    clock.advance(Duration::from_millis(20));
+    assert!(!button.read());
+    assert!(!button.was_pressed() && !button.was_released());
+
+    level.set(false);
+    assert!(button.read());
+    assert!(button.was_pressed());
+    let pressed_at = clock.now();
+    assert_eq!(button.last_change(), pressed_at);
+
+    // Bounces within the debounce time are ignored
+    level.set(true);
+    clock.advance(Duration::from_millis(5));
+    assert!(button.read());
+    assert!(!button.was_released());
+    level.set(false);
+    clock.advance(Duration::from_millis(5));
+    assert!(button.read());
+    assert!(!button.was_pressed());
+    assert_eq!(button.last_change(), pressed_at);
+
+    // Once it has passed, the next change counts
+    level.set(true);
+    clock.advance(Duration::from_millis(1));
+    assert!(!button.read());
+    assert!(button.was_released());
+  }
+
+  #[test]
+  fn press_durations() {
+    let (mut button, level, clock) = button();
+    clock.advance(Duration::from_millis(20));
+    level.set(false);
+    button.read();
+    clock.advance(Duration::from_millis(60));
+    button.read();
+    assert!(button.pressed_for(60) && !button.pressed_for(61));
+    assert!(!button.released_for(0));
+
+    // Across the wrap of a u32 millisecond count
+    clock.advance(Duration::from_millis(40));
+    level.set(true);
+    button.read();
+    assert!(button.was_released_for(100) && !button.was_released_for(101));
+
+    clock.advance(Duration::from_millis(30));
+    button.read();
+    assert!(!button.was_released_for(0));
+    assert!(button.released_for(30) && !button.released_for(31));
+    assert!(!button.pressed_for(0));
+  }
+
+  #[test]
+  fn edge_queue_keeps_short_presses() {
+    let (mut button, _level, clock) = button();
+    let queue = Arc::new(EdgeQueue::new());
+    button.set_edge_queue(Some(queue.clone()));
+    clock.advance(Duration::from_millis(20));
+    button.read();
+
+    // Pressed for 30ms between two reads, with a bounce right after the press
+    let start = clock.now();
+    for ms in [5, 6, 7, 35] {
+      queue.push(start + Duration::from_millis(ms));
+    }
+    clock.advance(Duration::from_millis(50));
+
+    assert!(button.read());
+    assert!(button.was_pressed());
+    assert_eq!(button.last_change(), start + Duration::from_millis(5));
+    assert!(!button.read());
+    assert!(button.was_released_for(30) && !button.was_released_for(31));
+    assert!(!button.read());
+    assert!(!button.was_released());
+  }
+
+  #[test]
+  fn edge_queue_catches_up_from_the_pin() {
+    let (mut button, level, clock) = button();
+    let queue = Arc::new(EdgeQueue::new());
+    button.set_edge_queue(Some(queue.clone()));
+    clock.advance(Duration::from_millis(20));
+    button.read();
+
+    // Only the press was queued, the button is up again by the next read
+    let pressed_at = clock.now() + Duration::from_millis(5);
+    queue.push(pressed_at);
+    clock.advance(Duration::from_millis(50));
+    assert!(button.read());
+    assert!(button.was_pressed());
+    assert_eq!(button.last_change(), pressed_at);
+    assert!(!button.read());
+    assert!(button.was_released());
+    assert_eq!(button.last_change(), clock.now());
+
+    // A change the queue missed while the button is held
+    level.set(false);
+    clock.advance(Duration::from_millis(20));
+    assert!(button.read());
+    assert!(button.was_pressed());
+    assert!(button.read());
+    assert!(!button.was_pressed());
+  }
+
+  fn events(events: &[Axp192Event]) -> Axp192Events {
+    events.iter().copied().collect()
+  }
+
+  #[test]
+  fn power_button_reports_press_then_release() {
+    let clock = ManualClock::default();
+    let mut button = PowerButton::with_clock(clock.clone());
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(button.read(&Axp192Events::empty()), None);
+    assert!(!button.pressed() && !button.was_pressed() && !button.was_released());
+
+    clock.advance(Duration::from_millis(10));
+    let short = events(&[Axp192Event::PekShortPress, Axp192Event::VbusInserted]);
+    assert_eq!(button.read(&short), Some(ButtonEvent::Click));
+    assert!(button.pressed() && button.was_pressed() && button.was_short_pressed());
+    assert_eq!(button.last_change(), Instant::from_millis(110));
+
+    clock.advance(Duration::from_millis(10));
+    assert_eq!(button.read(&Axp192Events::empty()), None);
+    assert!(!button.pressed() && button.was_released());
+    assert_eq!(button.last_change(), Instant::from_millis(120));
+
+    button.read(&Axp192Events::empty());
+    assert!(!button.was_released());
+    assert_eq!(button.last_change(), Instant::from_millis(120));
+  }
+
+  #[test]
+  fn power_button_long_press_wins() {
+    let mut button = PowerButton::with_clock(ManualClock::default());
+    let both = events(&[Axp192Event::PekShortPress, Axp192Event::PekLongPress]);
+    assert_eq!(button.read(&both), Some(ButtonEvent::LongPress));
+    assert!(button.was_long_pressed());
+  }
+
+  #[test]
+  fn power_button_keeps_back_to_back_presses() {
+    let mut button = PowerButton::with_clock(ManualClock::default());
+    let short = events(&[Axp192Event::PekShortPress]);
+    let long = events(&[Axp192Event::PekLongPress]);
+    assert_eq!(button.read(&short), Some(ButtonEvent::Click));
+    assert_eq!(button.read(&long), None);
+    assert!(button.was_released());
+    assert_eq!(
+      button.read(&Axp192Events::empty()),
+      Some(ButtonEvent::LongPress)
+    );
+    assert!(button.was_pressed());
+    assert_eq!(button.read(&Axp192Events::empty()), None);
+    assert!(button.was_released());
+  }
 }
@@ -0,0 +1,300 @@
+use crate::vector::{Acceleration, AngularRate, Vector3};
+
+/// Corrections applied to IMU readings.
+///
+/// A corrected accelerometer reading is `(raw - accel_offset) * accel_scale` per axis, a corrected
+/// gyroscope reading is `raw - gyro_bias`. The default leaves readings unchanged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+  pub gyro_bias: AngularRate,
+  pub accel_offset: Acceleration,
+  pub accel_scale: Vector3,
+}
+
+/// Reasons a stored calibration could not be restored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+  /// The blob is not `Calibration::SIZE` bytes long
+  InvalidLength,
+  /// The blob was not written by `Calibration::to_bytes` or by an incompatible version
+  UnknownFormat,
+  /// The blob is corrupted
+  ChecksumMismatch,
+}
+
+impl Calibration {
+  /// Size of the serialized calibration in bytes.
+  pub const SIZE: usize = 43;
+
+  const MAGIC: [u8; 4] = *b"IMUC";
+  const VERSION: u8 = 1;
+
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn apply_accel(&self, accel: Acceleration) -> Acceleration {
+    (accel - self.accel_offset)
+      .vector()
+      .scale(self.accel_scale)
+      .into()
+  }
+
+  pub fn apply_gyro(&self, gyro: AngularRate) -> AngularRate {
+    gyro - self.gyro_bias
+  }
+
+  /// Serialize into a versioned, checksummed blob, e.g. to store it in NVS.
+  pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+    let mut bytes = [0x00u8; Self::SIZE];
+    bytes[0..4].copy_from_slice(&Self::MAGIC);
+    bytes[4] = Self::VERSION;
+    for (i, value) in self.values().iter().enumerate() {
+      let at = 5 + i * 4;
+      bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    let checksum = fletcher16(&bytes[..Self::SIZE - 2]);
+    bytes[Self::SIZE - 2..].copy_from_slice(&checksum.to_le_bytes());
+    bytes
+  }
+
+  /// Restore a calibration written by `to_bytes`.
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+    if bytes.len() != Self::SIZE {
+      return Err(DecodeError::InvalidLength);
+    }
+    if bytes[0..4] != Self::MAGIC || bytes[4] != Self::VERSION {
+      return Err(DecodeError::UnknownFormat);
+    }
+    let checksum = u16::from_le_bytes([bytes[Self::SIZE - 2], bytes[Self::SIZE - 1]]);
+    if fletcher16(&bytes[..Self::SIZE - 2]) != checksum {
+      return Err(DecodeError::ChecksumMismatch);
+    }
+
+    let mut values = [0.0f32; 9];
+    for (i, value) in values.iter_mut().enumerate() {
+      let at = 5 + i * 4;
+      *value = f32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    }
+    let [gx, gy, gz, ox, oy, oz, sx, sy, sz] = values;
+    Ok(Self {
+      gyro_bias: AngularRate::new(gx, gy, gz),
+      accel_offset: Acceleration::new(ox, oy, oz),
+      accel_scale: Vector3::new(sx, sy, sz),
+    })
+  }
+
+  fn values(&self) -> [f32; 9] {
+    let (g, o, s) = (self.gyro_bias, self.accel_offset, self.accel_scale);
+    [g.x, g.y, g.z, o.x, o.y, o.z, s.x, s.y, s.z]
+  }
+}
+
+impl Default for Calibration {
+  fn default() -> Self {
+    Self {
+      gyro_bias: AngularRate::ZERO,
+      accel_offset: Acceleration::ZERO,
+      accel_scale: Vector3::new(1.0, 1.0, 1.0),
+    }
+  }
+}
+
+/// Accelerometer offset and scale from measurements with each axis pointing straight up and
+/// straight down.
+///
+/// Hold the device still in each of the six positions and pass the mean reading, e.g. from
+/// `MPU6886::measure_accel`, to `add`. The positions can be recorded in any order.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SixPositionCalibration {
+  // +X, -X, +Y, -Y, +Z, -Z
+  positions: [Option<f32>; 6],
+}
+
+impl SixPositionCalibration {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Record the mean reading of one resting position, replacing an earlier reading of the
+  /// same position.
+  ///
+  /// Returns `false` if no axis is clearly aligned with gravity.
+  pub fn add(&mut self, accel: Acceleration) -> bool {
+    let axes = [accel.x, accel.y, accel.z];
+    let (axis, &value) = axes
+      .iter()
+      .enumerate()
+      .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+      .unwrap();
+    let others = axes
+      .iter()
+      .enumerate()
+      .filter(|&(i, _)| i != axis)
+      .fold(0.0f32, |max, (_, v)| max.max(v.abs()));
+    if value.abs() < 0.5 || others > 0.25 {
+      return false;
+    }
+
+    let index = axis * 2 + if value < 0.0 { 1 } else { 0 };
+    self.positions[index] = Some(value);
+    true
+  }
+
+  pub fn is_complete(&self) -> bool {
+    self.positions.iter().all(Option::is_some)
+  }
+
+  /// `calibration` with the accelerometer offset and scale replaced, once all positions were
+  /// recorded.
+  pub fn solve(&self, calibration: Calibration) -> Option<Calibration> {
+    let axis = |i: usize| {
+      let up = self.positions[i * 2]?;
+      let down = self.positions[i * 2 + 1]?;
+      Some(((up + down) / 2.0, 2.0 / (up - down)))
+    };
+    let (ox, sx) = axis(0)?;
+    let (oy, sy) = axis(1)?;
+    let (oz, sz) = axis(2)?;
+    Some(Calibration {
+      accel_offset: Acceleration::new(ox, oy, oz),
+      accel_scale: Vector3::new(sx, sy, sz),
+      ..calibration
+    })
+  }
+}
+
+pub(crate) fn fletcher16(bytes: &[u8]) -> u16 {
+  let (mut sum1, mut sum2) = (0u16, 0u16);
+  for &byte in bytes {
+    sum1 = (sum1 + byte as u16) % 255;
+    sum2 = (sum2 + sum1) % 255;
+  }
+  (sum2 << 8) | sum1
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn calibration() -> Calibration {
+    Calibration {
+      gyro_bias: AngularRate::new(0.5, -1.25, 3.0),
+      accel_offset: Acceleration::new(0.02, -0.015, 0.04),
+      accel_scale: Vector3::new(1.01, 0.99, 0.985),
+    }
+  }
+
+  #[test]
+  fn round_trip() {
+    let bytes = calibration().to_bytes();
+    assert_eq!(&bytes[0..5], b"IMUC\x01");
+    assert_eq!(Calibration::from_bytes(&bytes), Ok(calibration()));
+    assert_eq!(
+      Calibration::from_bytes(&Calibration::default().to_bytes()),
+      Ok(Calibration::default())
+    );
+  }
+
+  #[test]
+  fn rejects_corrupted_blobs() {
+    let bytes = calibration().to_bytes();
+    assert_eq!(
+      Calibration::from_bytes(&bytes[..Calibration::SIZE - 1]),
+      Err(DecodeError::InvalidLength)
+    );
+    assert_eq!(
+      Calibration::from_bytes(&[bytes.as_slice(), &[0]].concat()),
+      Err(DecodeError::InvalidLength)
+    );
+
+    let mut bad_magic = bytes;
+    bad_magic[0] = b'X';
+    assert_eq!(
+      Calibration::from_bytes(&bad_magic),
+      Err(DecodeError::UnknownFormat)
+    );
+    let mut bad_version = bytes;
+    bad_version[4] = 2;
+    assert_eq!(
+      Calibration::from_bytes(&bad_version),
+      Err(DecodeError::UnknownFormat)
+    );
+
+    for at in [5, 20, Calibration::SIZE - 3, Calibration::SIZE - 1] {
+      let mut corrupted = bytes;
+      corrupted[at] ^= 0x10;
+      assert_eq!(
+        Calibration::from_bytes(&corrupted),
+        Err(DecodeError::ChecksumMismatch),
+        "byte {at}"
+      );
+    }
+  }
+
+  #[test]
+  fn applies_corrections() {
+    let calibration = calibration();
+    let accel = calibration.apply_accel(Acceleration::new(1.02, -0.015, 0.04));
+    assert!((accel.x - 1.01).abs() < 1e-6);
+    assert!(accel.y.abs() < 1e-6 && accel.z.abs() < 1e-6);
+    let gyro = calibration.apply_gyro(AngularRate::new(0.5, 0.0, 0.0));
+    assert_eq!((gyro.x, gyro.y, gyro.z), (0.0, 1.25, -3.0));
+    let unchanged = Acceleration::new(0.1, 0.2, 0.3);
+    assert_eq!(Calibration::default().apply_accel(unchanged), unchanged);
+  }
+
+  #[test]
+  fn six_position_solve() {
+    let offset = [0.03f32, -0.02, 0.05];
+    let scale = [1.02f32, 0.98, 0.995];
+    let reading = |axis: usize, sign: f32| {
+      let mut values = [0.01f32, -0.01, 0.02];
+      values[axis] = offset[axis] + sign / scale[axis];
+      Acceleration::new(values[0], values[1], values[2])
+    };
+
+    let mut six = SixPositionCalibration::new();
+    assert!(six.solve(Calibration::default()).is_none());
+    for axis in [2, 0, 1] {
+      assert!(six.add(reading(axis, 1.0)));
+      assert!(six.add(reading(axis, -1.0)));
+    }
+    assert!(six.is_complete());
+
+    let gyro_bias = AngularRate::new(1.0, 2.0, 3.0);
+    let solved = six
+      .solve(Calibration {
+        gyro_bias,
+        ..Default::default()
+      })
+      .unwrap();
+    assert_eq!(solved.gyro_bias, gyro_bias);
+    let solved_offset = solved.accel_offset;
+    let solved_scale = solved.accel_scale;
+    for (i, (o, s)) in [
+      (solved_offset.x, solved_scale.x),
+      (solved_offset.y, solved_scale.y),
+      (solved_offset.z, solved_scale.z),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+      assert!((o - offset[i]).abs() < 1e-5, "offset {i}: {o}");
+      assert!((s - scale[i]).abs() < 1e-5, "scale {i}: {s}");
+    }
+    // Corrected readings of the positions are exactly ±1g
+    let up = solved.apply_accel(reading(1, 1.0));
+    assert!((up.y - 1.0).abs() < 1e-5);
+  }
+
+  #[test]
+  fn six_position_rejects_tilted_readings() {
+    let mut six = SixPositionCalibration::new();
+    assert!(!six.add(Acceleration::new(0.7, 0.7, 0.0)));
+    assert!(!six.add(Acceleration::new(0.0, 0.0, 0.3)));
+    assert!(six.add(Acceleration::new(0.0, 0.0, 1.0)));
+    assert!(!six.is_complete());
+    assert!(six.solve(Calibration::default()).is_none());
+  }
+}
@@ -0,0 +1,233 @@
+use alloc::collections::VecDeque;
+use core::time::Duration;
+
+use crate::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonEvent {
+  /// A short press, reported once no second press followed.
+  Click,
+  /// Two short presses in a row.
+  DoubleClick,
+  /// The button has been held for `long_press`.
+  LongPress,
+  /// Sent every `repeat_interval` while the button stays held after a long press.
+  HoldRepeat,
+  /// The button went up, after any kind of press.
+  Release,
+}
+
+/// Timings of `ClickRecognizer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClickConfig {
+  /// Time after a click in which a second one makes it a double click. Zero reports every short
+  /// press as a click right away.
+  pub double_click: Duration,
+  /// Holding the button this long makes it a long press instead of a click.
+  pub long_press: Duration,
+  /// Interval of `HoldRepeat` after a long press, zero for none.
+  pub repeat_interval: Duration,
+}
+
+impl Default for ClickConfig {
+  fn default() -> Self {
+    Self {
+      double_click: Duration::from_millis(300),
+      long_press: Duration::from_millis(1000),
+      repeat_interval: Duration::from_millis(200),
+    }
+  }
+}
+
+/// Turns the level of a button into clicks, long presses and repeats.
+///
+/// Call `update` with the debounced level after every `Button::read`, e.g.
+/// `recognizer.update(SystemClock.now(), m5.btn_a().pressed())` after `M5::update`, and collect
+/// the events with `drain_events`. The time must come from the clock of the `Button`, so that it
+/// is never earlier than `Button::last_change`.
+pub struct ClickRecognizer {
+  config: ClickConfig,
+  events: VecDeque<ButtonEvent>,
+  pressed: bool,
+  pressed_since: Instant,
+  long_press: bool,
+  next_repeat: Instant,
+  /// Release time of a click that may still become a double click.
+  pending_click: Option<Instant>,
+  second_press: bool,
+}
+
+impl ClickRecognizer {
+  pub fn new(config: ClickConfig) -> Self {
+    Self {
+      config,
+      events: VecDeque::new(),
+      pressed: false,
+      pressed_since: Instant::default(),
+      long_press: false,
+      next_repeat: Instant::default(),
+      pending_click: None,
+      second_press: false,
+    }
+  }
+
+  pub fn config(&self) -> &ClickConfig {
+    &self.config
+  }
+
+  /// Forget all state and pending events.
+  pub fn reset(&mut self) {
+    *self = Self::new(self.config);
+  }
+
+  /// Process the button level at `now`.
+  pub fn update(&mut self, now: Instant, pressed: bool) {
+    if let Some(released) = self.pending_click {
+      if now - released > self.config.double_click {
+        self.events.push_back(ButtonEvent::Click);
+        self.pending_click = None;
+      }
+    }
+
+    if pressed && !self.pressed {
+      self.pressed = true;
+      self.pressed_since = now;
+      self.long_press = false;
+      self.second_press = self.pending_click.take().is_some();
+    } else if !pressed && self.pressed {
+      self.pressed = false;
+      self.events.push_back(ButtonEvent::Release);
+      if !self.long_press {
+        if self.second_press {
+          self.events.push_back(ButtonEvent::DoubleClick);
+        } else if self.config.double_click.is_zero() {
+          self.events.push_back(ButtonEvent::Click);
+        } else {
+          self.pending_click = Some(now);
+        }
+      }
+      self.second_press = false;
+    }
+
+    if !self.pressed {
+      return;
+    }
+    if !self.long_press {
+      if now - self.pressed_since >= self.config.long_press {
+        self.long_press = true;
+        // The first press of a would-be double click was a click after all
+        if self.second_press {
+          self.events.push_back(ButtonEvent::Click);
+          self.second_press = false;
+        }
+        self.events.push_back(ButtonEvent::LongPress);
+        self.next_repeat = now + self.config.repeat_interval;
+      }
+    } else if !self.config.repeat_interval.is_zero() && now >= self.next_repeat {
+      self.events.push_back(ButtonEvent::HoldRepeat);
+      self.next_repeat = self.next_repeat + self.config.repeat_interval;
+    }
+  }
+
+  /// Events recognized since the last call, oldest first.
+  pub fn drain_events(&mut self) -> impl Iterator<Item = ButtonEvent> + '_ {
+    self.events.drain(..)
+  }
+}
+
+impl Default for ClickRecognizer {
+  fn default() -> Self {
+    Self::new(ClickConfig::default())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::vec::Vec;
+
+  /// Update every 10ms from `start_ms` up to `end_ms` with the button held or not.
+  fn level(recognizer: &mut ClickRecognizer, start_ms: u64, end_ms: u64, pressed: bool) {
+    for ms in (start_ms..end_ms).step_by(10) {
+      recognizer.update(Instant::from_millis(ms), pressed);
+    }
+  }
+
+  fn events(recognizer: &mut ClickRecognizer) -> Vec<ButtonEvent> {
+    recognizer.drain_events().collect()
+  }
+
+  #[test]
+  fn click_after_double_click_time() {
+    let mut recognizer = ClickRecognizer::default();
+    level(&mut recognizer, 0, 100, true);
+    level(&mut recognizer, 100, 300, false);
+    assert_eq!(events(&mut recognizer), [ButtonEvent::Release]);
+    level(&mut recognizer, 300, 500, false);
+    assert_eq!(events(&mut recognizer), [ButtonEvent::Click]);
+  }
+
+  #[test]
+  fn click_right_away_without_double_click() {
+    let mut recognizer = ClickRecognizer::new(ClickConfig {
+      double_click: Duration::ZERO,
+      ..Default::default()
+    });
+    level(&mut recognizer, 0, 100, true);
+    level(&mut recognizer, 100, 110, false);
+    assert_eq!(
+      events(&mut recognizer),
+      [ButtonEvent::Release, ButtonEvent::Click]
+    );
+  }
+
+  #[test]
+  fn double_click() {
+    let mut recognizer = ClickRecognizer::default();
+    level(&mut recognizer, 0, 100, true);
+    level(&mut recognizer, 100, 200, false);
+    level(&mut recognizer, 200, 300, true);
+    level(&mut recognizer, 300, 1000, false);
+    assert_eq!(
+      events(&mut recognizer),
+      [
+        ButtonEvent::Release,
+        ButtonEvent::Release,
+        ButtonEvent::DoubleClick
+      ]
+    );
+  }
+
+  #[test]
+  fn long_press_repeats() {
+    let mut recognizer = ClickRecognizer::default();
+    let start_ms = u32::MAX as u64 - 500;
+    level(&mut recognizer, start_ms, start_ms + 1450, true);
+    level(&mut recognizer, start_ms + 1450, start_ms + 2000, false);
+    assert_eq!(
+      events(&mut recognizer),
+      [
+        ButtonEvent::LongPress,
+        ButtonEvent::HoldRepeat,
+        ButtonEvent::HoldRepeat,
+        ButtonEvent::Release
+      ]
+    );
+  }
+
+  #[test]
+  fn second_press_held_long() {
+    let mut recognizer = ClickRecognizer::default();
+    level(&mut recognizer, 0, 100, true);
+    level(&mut recognizer, 100, 200, false);
+    level(&mut recognizer, 200, 1250, true);
+    assert_eq!(
+      events(&mut recognizer),
+      [
+        ButtonEvent::Release,
+        ButtonEvent::Click,
+        ButtonEvent::LongPress
+      ]
+    );
+  }
+}
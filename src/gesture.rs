@@ -0,0 +1,542 @@
+use alloc::collections::VecDeque;
+use core::time::Duration;
+
+use libm::sinf;
+
+use crate::time::Instant;
+use crate::vector::{Acceleration, Vector3};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gesture {
+  /// Shaken back and forth.
+  Shake,
+  /// A single short knock, reported once no second one followed.
+  Tap,
+  DoubleTap,
+  /// The left edge went down.
+  TiltLeft,
+  /// The right edge went down.
+  TiltRight,
+  /// Lying with the screen facing down.
+  FaceDown,
+  /// Falling, i.e. the accelerometer measures almost nothing.
+  FreeFall,
+  /// A hit much harder than a tap, e.g. landing after a fall.
+  Impact,
+}
+
+/// Thresholds of `GestureRecognizer`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GestureConfig {
+  /// Minimum acceleration on top of gravity for a tap.
+  pub tap_threshold_g: f32,
+  /// Longer spikes are movements, not taps.
+  pub tap_max_duration: Duration,
+  /// Time after a tap in which a second one makes it a double tap.
+  pub double_tap_window: Duration,
+  /// Minimum acceleration on top of gravity for a shake stroke.
+  pub shake_threshold_g: f32,
+  /// Strokes in opposite directions that make a shake.
+  pub shake_strokes: u8,
+  /// Time in which all strokes of a shake have to happen.
+  pub shake_window: Duration,
+  /// Angle from level that counts as tilted.
+  pub tilt_angle_deg: f32,
+  /// Time the screen has to face down.
+  pub face_down_time: Duration,
+  /// Total acceleration below which the device is falling.
+  pub free_fall_threshold_g: f32,
+  /// Time the device has to fall.
+  pub free_fall_time: Duration,
+  /// Total acceleration above which the device was hit.
+  pub impact_threshold_g: f32,
+  /// Time constant of the gravity estimate the other gestures are measured against.
+  pub gravity_time_constant: Duration,
+}
+
+impl Default for GestureConfig {
+  fn default() -> Self {
+    Self {
+      tap_threshold_g: 0.8,
+      tap_max_duration: Duration::from_millis(60),
+      double_tap_window: Duration::from_millis(300),
+      shake_threshold_g: 1.0,
+      shake_strokes: 4,
+      shake_window: Duration::from_millis(1000),
+      tilt_angle_deg: 30.0,
+      face_down_time: Duration::from_millis(500),
+      free_fall_threshold_g: 0.3,
+      free_fall_time: Duration::from_millis(80),
+      impact_threshold_g: 3.0,
+      gravity_time_constant: Duration::from_millis(200),
+    }
+  }
+}
+
+/// Recognizes gestures from a stream of accelerometer readings.
+///
+/// Feed every reading to `update` and collect the recognized gestures with `drain_events`.
+/// Readings should be in the LCD frame, see `AxisRemap::LCD`, so left and right match the screen.
+pub struct GestureRecognizer {
+  config: GestureConfig,
+  events: VecDeque<Gesture>,
+  last_time: Option<Instant>,
+  gravity: Vector3,
+  // Tap
+  spike_start: Option<Instant>,
+  spike_impact: bool,
+  taps: u8,
+  last_tap: Instant,
+  // Shake
+  strokes: u8,
+  stroke_direction: Option<Vector3>,
+  first_stroke: Instant,
+  shake_until: Option<Instant>,
+  // Orientation
+  tilt: Option<Gesture>,
+  face_down_since: Option<Instant>,
+  face_down: bool,
+  falling_since: Option<Instant>,
+  falling: bool,
+}
+
+impl GestureRecognizer {
+  pub fn new(config: GestureConfig) -> Self {
+    Self {
+      config,
+      events: VecDeque::new(),
+      last_time: None,
+      gravity: Vector3::ZERO,
+      spike_start: None,
+      spike_impact: false,
+      taps: 0,
+      last_tap: Instant::default(),
+      strokes: 0,
+      stroke_direction: None,
+      first_stroke: Instant::default(),
+      shake_until: None,
+      tilt: None,
+      face_down_since: None,
+      face_down: false,
+      falling_since: None,
+      falling: false,
+    }
+  }
+
+  pub fn config(&self) -> &GestureConfig {
+    &self.config
+  }
+
+  /// Forget all state and pending events.
+  pub fn reset(&mut self) {
+    *self = Self::new(self.config);
+  }
+
+  /// Process one reading taken at `now`, e.g. `SystemClock.now()`.
+  pub fn update(&mut self, now: Instant, accel: Acceleration) {
+    let accel = accel.vector();
+    let dt = match self.last_time {
+      Some(last) => (now - last).as_secs_f32(),
+      None => {
+        self.gravity = accel;
+        0.0
+      }
+    };
+    self.last_time = Some(now);
+
+    let alpha = dt / (self.config.gravity_time_constant.as_secs_f32() + dt);
+    self.gravity = self.gravity + (accel - self.gravity) * alpha;
+    let dynamic = accel - self.gravity;
+
+    self.update_free_fall(now, accel.norm());
+    self.update_shake(now, dynamic);
+    self.update_tap(now, accel.norm(), dynamic.norm());
+    self.update_orientation(now, dynamic.norm());
+  }
+
+  /// Gestures recognized since the last call, oldest first.
+  pub fn drain_events(&mut self) -> impl Iterator<Item = Gesture> + '_ {
+    self.events.drain(..)
+  }
+
+  fn update_free_fall(&mut self, now: Instant, magnitude: f32) {
+    if magnitude < self.config.free_fall_threshold_g {
+      let since = *self.falling_since.get_or_insert(now);
+      if !self.falling && now - since >= self.config.free_fall_time {
+        self.falling = true;
+        self.events.push_back(Gesture::FreeFall);
+      }
+    } else {
+      self.falling_since = None;
+      self.falling = false;
+    }
+  }
+
+  fn update_shake(&mut self, now: Instant, dynamic: Vector3) {
+    if let Some(until) = self.shake_until {
+      if now < until {
+        return;
+      }
+      self.shake_until = None;
+    }
+
+    if self.strokes > 0 && now - self.first_stroke > self.config.shake_window {
+      self.strokes = 0;
+      self.stroke_direction = None;
+    }
+    if dynamic.norm() < self.config.shake_threshold_g {
+      return;
+    }
+
+    // A stroke counts when it goes against the previous one
+    let reversed = match self.stroke_direction {
+      Some(direction) => direction.dot(dynamic) < 0.0,
+      None => true,
+    };
+    if !reversed {
+      return;
+    }
+    if self.strokes == 0 {
+      self.first_stroke = now;
+    }
+    self.strokes += 1;
+    self.stroke_direction = Some(dynamic);
+
+    if self.strokes >= self.config.shake_strokes {
+      self.events.push_back(Gesture::Shake);
+      self.strokes = 0;
+      self.stroke_direction = None;
+      self.taps = 0;
+      self.shake_until = Some(now + self.config.shake_window);
+    }
+  }
+
+  fn update_tap(&mut self, now: Instant, magnitude: f32, dynamic: f32) {
+    if magnitude > self.config.impact_threshold_g && !self.spike_impact {
+      self.spike_impact = true;
+      self.events.push_back(Gesture::Impact);
+    }
+
+    match self.spike_start {
+      None if dynamic > self.config.tap_threshold_g => {
+        self.spike_start = Some(now);
+      }
+      Some(start) if dynamic < self.config.tap_threshold_g / 2.0 => {
+        let short = now - start <= self.config.tap_max_duration;
+        if short && !self.spike_impact && self.shake_until.is_none() {
+          self.taps += 1;
+          self.last_tap = now;
+        }
+        self.spike_start = None;
+        self.spike_impact = false;
+      }
+      _ => {}
+    }
+
+    if self.taps == 2 {
+      self.events.push_back(Gesture::DoubleTap);
+      self.taps = 0;
+    } else if self.taps == 1 && now - self.last_tap > self.config.double_tap_window {
+      self.events.push_back(Gesture::Tap);
+      self.taps = 0;
+    }
+  }
+
+  fn update_orientation(&mut self, now: Instant, dynamic: f32) {
+    let Some(gravity) = self.gravity.normalized() else {
+      return;
+    };
+
+    if gravity.z < -0.8 {
+      let since = *self.face_down_since.get_or_insert(now);
+      if !self.face_down && now - since >= self.config.face_down_time {
+        self.face_down = true;
+        self.events.push_back(Gesture::FaceDown);
+      }
+    } else if gravity.z > -0.5 {
+      self.face_down_since = None;
+      self.face_down = false;
+    }
+
+    // Only tilts of a device held steady count
+    let moving = dynamic > 0.25 || self.strokes > 0 || self.shake_until.is_some();
+    if moving || self.falling || gravity.z < -0.5 {
+      return;
+    }
+    // The accelerometer measures the reaction to gravity, so the edge going down reads negative
+    let tilted = sinf(self.config.tilt_angle_deg.to_radians());
+    if self.tilt.is_none() {
+      if gravity.x < -tilted {
+        self.tilt = Some(Gesture::TiltRight);
+        self.events.push_back(Gesture::TiltRight);
+      } else if gravity.x > tilted {
+        self.tilt = Some(Gesture::TiltLeft);
+        self.events.push_back(Gesture::TiltLeft);
+      }
+    } else if gravity.x.abs() < tilted / 2.0 {
+      self.tilt = None;
+    }
+  }
+}
+
+impl Default for GestureRecognizer {
+  fn default() -> Self {
+    Self::new(GestureConfig::default())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::vec;
+  use alloc::vec::Vec;
+
+  const PERIOD_MS: u64 = 10;
+
+  const FLAT: Acceleration = Acceleration::new(0.0, 0.0, 1.0);
+
+  /// Feeds readings at `PERIOD_MS` to a recognizer.
+  struct Trace {
+    recognizer: GestureRecognizer,
+    now: Instant,
+    strokes: u8,
+  }
+
+  impl Trace {
+    fn new(config: GestureConfig) -> Self {
+      Self::starting_at(config, Instant::default())
+    }
+
+    fn starting_at(config: GestureConfig, now: Instant) -> Self {
+      let mut trace = Self {
+        recognizer: GestureRecognizer::new(config),
+        now,
+        strokes: 0,
+      };
+      trace.hold(FLAT, 500);
+      trace
+    }
+
+    /// Feed `accel` for `ms` and return the gestures recognized meanwhile.
+    fn hold(&mut self, accel: Acceleration, ms: u64) -> Vec<Gesture> {
+      for _ in 0..ms / PERIOD_MS {
+        self.recognizer.update(self.now, accel);
+        self.now = self.now + Duration::from_millis(PERIOD_MS);
+      }
+      self.recognizer.drain_events().collect()
+    }
+
+    /// One reading of `accel` on a device lying flat.
+    fn knock(&mut self, accel: Acceleration) -> Vec<Gesture> {
+      let mut events = self.hold(accel, PERIOD_MS);
+      events.extend(self.hold(FLAT, PERIOD_MS));
+      events
+    }
+
+    /// Shake along X with `strokes` strokes of 80ms, each against the previous one.
+    fn shake(&mut self, strokes: u8) -> Vec<Gesture> {
+      let mut events = Vec::new();
+      for _ in 0..strokes {
+        self.strokes += 1;
+        let x = if self.strokes % 2 == 1 { 1.5 } else { -1.5 };
+        events.extend(self.hold(Acceleration::new(x, 0.0, 1.0), 80));
+      }
+      events
+    }
+  }
+
+  /// A spike of 0.9g on top of gravity.
+  const TAP: Acceleration = Acceleration::new(0.0, 0.0, 1.9);
+
+  #[test]
+  fn tap() {
+    let mut trace = Trace::new(GestureConfig::default());
+    assert_eq!(trace.knock(TAP), vec![]);
+    // Reported once the double tap window passed
+    assert_eq!(trace.hold(FLAT, 280), vec![]);
+    assert_eq!(trace.hold(FLAT, 30), vec![Gesture::Tap]);
+    assert_eq!(trace.hold(FLAT, 1000), vec![]);
+  }
+
+  #[test]
+  fn double_tap() {
+    let mut trace = Trace::new(GestureConfig::default());
+    assert_eq!(trace.knock(TAP), vec![]);
+    assert_eq!(trace.hold(FLAT, 150), vec![]);
+    assert_eq!(trace.knock(TAP), vec![Gesture::DoubleTap]);
+    assert_eq!(trace.hold(FLAT, 1000), vec![]);
+
+    // Too far apart for a double tap
+    assert_eq!(trace.knock(TAP), vec![]);
+    assert_eq!(trace.hold(FLAT, 400), vec![Gesture::Tap]);
+    assert_eq!(trace.knock(TAP), vec![]);
+    assert_eq!(trace.hold(FLAT, 400), vec![Gesture::Tap]);
+  }
+
+  #[test]
+  fn long_spikes_are_not_taps() {
+    let mut trace = Trace::new(GestureConfig::default());
+    assert_eq!(trace.hold(TAP, 100), vec![]);
+    assert_eq!(trace.hold(FLAT, 1000), vec![]);
+  }
+
+  #[test]
+  fn shake() {
+    let mut trace = Trace::new(GestureConfig::default());
+    assert_eq!(trace.shake(3), vec![]);
+    assert_eq!(trace.shake(1), vec![Gesture::Shake]);
+    assert_eq!(trace.hold(FLAT, 2000), vec![]);
+
+    // Too slow
+    for _ in 0..4 {
+      assert_eq!(trace.shake(1), vec![]);
+      assert_eq!(trace.hold(FLAT, 400), vec![]);
+    }
+  }
+
+  #[test]
+  fn taps_are_ignored_while_shaking() {
+    let mut trace = Trace::new(GestureConfig::default());
+    assert_eq!(trace.shake(4), vec![Gesture::Shake]);
+    assert_eq!(trace.hold(FLAT, 300), vec![]);
+    assert_eq!(trace.knock(TAP), vec![]);
+    assert_eq!(trace.hold(FLAT, 400), vec![]);
+
+    // Taps count again once the shake window is over
+    assert_eq!(trace.hold(FLAT, 500), vec![]);
+    assert_eq!(trace.knock(TAP), vec![]);
+    assert_eq!(trace.hold(FLAT, 400), vec![Gesture::Tap]);
+  }
+
+  #[test]
+  fn tilt() {
+    let mut trace = Trace::new(GestureConfig::default());
+    let right = Acceleration::new(-0.6, 0.0, 0.8);
+    let left = Acceleration::new(0.6, 0.0, 0.8);
+    assert_eq!(trace.hold(right, 1000), vec![Gesture::TiltRight]);
+    assert_eq!(trace.hold(FLAT, 1000), vec![]);
+    assert_eq!(trace.hold(left, 1000), vec![Gesture::TiltLeft]);
+    // Tilting further does not repeat the gesture
+    assert_eq!(trace.hold(Acceleration::new(0.8, 0.0, 0.6), 1000), vec![]);
+    assert_eq!(trace.hold(FLAT, 1000), vec![]);
+
+    // Slight tilts do not count
+    let slight = Acceleration::new(-0.4, 0.0, 0.92);
+    assert_eq!(trace.hold(slight, 1000), vec![]);
+  }
+
+  #[test]
+  fn face_down() {
+    let mut trace = Trace::new(GestureConfig::default());
+    let down = Acceleration::new(0.0, 0.0, -1.0);
+    assert_eq!(trace.hold(down, 500), vec![]);
+    assert_eq!(trace.hold(down, 500), vec![Gesture::FaceDown]);
+    assert_eq!(trace.hold(down, 2000), vec![]);
+    assert_eq!(trace.hold(FLAT, 1000), vec![]);
+    assert_eq!(trace.hold(down, 1000), vec![Gesture::FaceDown]);
+  }
+
+  #[test]
+  fn free_fall_and_impact() {
+    let mut trace = Trace::new(GestureConfig::default());
+    let falling = Acceleration::new(0.0, 0.0, 0.1);
+    assert_eq!(trace.hold(falling, 70), vec![]);
+    assert_eq!(trace.hold(falling, 30), vec![Gesture::FreeFall]);
+    assert_eq!(trace.hold(falling, 200), vec![]);
+    // The hit on landing is no tap
+    assert_eq!(
+      trace.knock(Acceleration::new(0.0, 0.0, 4.0)),
+      vec![Gesture::Impact]
+    );
+    assert_eq!(trace.hold(FLAT, 2000), vec![]);
+  }
+
+  #[test]
+  fn short_dips_are_no_free_fall() {
+    let mut trace = Trace::new(GestureConfig::default());
+    assert_eq!(trace.hold(Acceleration::new(0.0, 0.0, 0.1), 70), vec![]);
+    assert_eq!(trace.hold(FLAT, 1000), vec![]);
+  }
+
+  #[test]
+  fn thresholds() {
+    let config = GestureConfig {
+      tap_threshold_g: 1.0,
+      ..Default::default()
+    };
+    let mut trace = Trace::new(config);
+    assert_eq!(trace.knock(TAP), vec![]);
+    assert_eq!(trace.hold(FLAT, 1000), vec![]);
+
+    // 37° is no tilt any more, 53° is
+    let config = GestureConfig {
+      tilt_angle_deg: 45.0,
+      ..Default::default()
+    };
+    let mut trace = Trace::new(config);
+    assert_eq!(trace.hold(Acceleration::new(-0.6, 0.0, 0.8), 1000), vec![]);
+    assert_eq!(
+      trace.hold(Acceleration::new(-0.8, 0.0, 0.6), 1000),
+      vec![Gesture::TiltRight]
+    );
+
+    let config = GestureConfig {
+      shake_strokes: 6,
+      ..Default::default()
+    };
+    let mut trace = Trace::new(config);
+    assert_eq!(trace.shake(4), vec![]);
+    assert_eq!(trace.shake(2), vec![Gesture::Shake]);
+
+    // Not an impact, so a knock this hard is a tap
+    let config = GestureConfig {
+      impact_threshold_g: 5.0,
+      ..Default::default()
+    };
+    let mut trace = Trace::new(config);
+    assert_eq!(trace.knock(Acceleration::new(0.0, 0.0, 4.0)), vec![]);
+    assert_eq!(trace.hold(FLAT, 400), vec![Gesture::Tap]);
+
+    let config = GestureConfig {
+      double_tap_window: Duration::from_millis(100),
+      ..Default::default()
+    };
+    let mut trace = Trace::new(config);
+    assert_eq!(trace.knock(TAP), vec![]);
+    assert_eq!(trace.hold(FLAT, 150), vec![Gesture::Tap]);
+    assert_eq!(trace.knock(TAP), vec![]);
+
+    let config = GestureConfig {
+      face_down_time: Duration::from_millis(2000),
+      ..Default::default()
+    };
+    let mut trace = Trace::new(config);
+    let down = Acceleration::new(0.0, 0.0, -1.0);
+    assert_eq!(trace.hold(down, 1500), vec![]);
+    assert_eq!(trace.hold(down, 1000), vec![Gesture::FaceDown]);
+  }
+
+  #[test]
+  fn past_u32_milliseconds() {
+    // Where a u32 millisecond count would wrap
+    let start = Instant::from_millis(u32::MAX as u64 - 1000);
+    let mut trace = Trace::starting_at(GestureConfig::default(), start);
+    assert_eq!(trace.knock(TAP), vec![]);
+    assert_eq!(trace.hold(FLAT, 150), vec![]);
+    assert_eq!(trace.knock(TAP), vec![Gesture::DoubleTap]);
+
+    let mut trace = Trace::starting_at(GestureConfig::default(), start);
+    assert_eq!(trace.shake(4), vec![Gesture::Shake]);
+    assert_eq!(trace.hold(FLAT, 300), vec![]);
+    assert_eq!(trace.knock(TAP), vec![]);
+    assert_eq!(trace.hold(FLAT, 1500), vec![]);
+    assert_eq!(trace.knock(TAP), vec![]);
+    assert_eq!(trace.hold(FLAT, 400), vec![Gesture::Tap]);
+
+    let start = Instant::from_millis(u32::MAX as u64 - 700);
+    let mut trace = Trace::starting_at(GestureConfig::default(), start);
+    let down = Acceleration::new(0.0, 0.0, -1.0);
+    assert_eq!(trace.hold(down, 400), vec![]);
+    assert_eq!(trace.hold(down, 1000), vec![Gesture::FaceDown]);
+  }
+}
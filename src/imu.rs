@@ -0,0 +1,274 @@
+use embedded_hal::delay::DelayUs;
+
+use crate::mpu6886::{self, MotionSample, MPU6886};
+use crate::sh200q::{self, SH200Q};
+use crate::vector::{Acceleration, AngularRate};
+
+/// Full scale range of an accelerometer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccelRange {
+  G2,
+  G4,
+  G8,
+  G16,
+}
+
+/// Full scale range of a gyroscope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GyroRange {
+  Dps125,
+  Dps250,
+  Dps500,
+  Dps1000,
+  Dps2000,
+}
+
+/// Functionality shared by the IMUs fitted to M5StickC boards.
+///
+/// A range a sensor does not support is rejected with its `InvalidArgument` error.
+pub trait Imu {
+  type Error;
+
+  fn init<D: DelayUs>(&mut self, delay: &mut D) -> Result<(), Self::Error>;
+
+  fn get_accel_data(&mut self) -> Result<Acceleration, Self::Error>;
+
+  fn get_gyro_data(&mut self) -> Result<AngularRate, Self::Error>;
+
+  /// Die temperature in degrees Celsius.
+  fn get_temp_data(&mut self) -> Result<f32, Self::Error>;
+
+  fn set_accel_range(&mut self, range: AccelRange) -> Result<(), Self::Error>;
+
+  fn set_gyro_range(&mut self, range: GyroRange) -> Result<(), Self::Error>;
+
+  fn get_motion_data(&mut self) -> Result<MotionSample, Self::Error> {
+    Ok(MotionSample {
+      accel: self.get_accel_data()?,
+      gyro: self.get_gyro_data()?,
+      temp_c: self.get_temp_data()?,
+    })
+  }
+}
+
+impl<I2C> Imu for MPU6886<I2C>
+where
+  I2C: embedded_hal::i2c::I2c,
+{
+  type Error = mpu6886::Error<I2C::Error>;
+
+  fn init<D: DelayUs>(&mut self, delay: &mut D) -> Result<(), Self::Error> {
+    MPU6886::init(self, delay)
+  }
+
+  fn get_accel_data(&mut self) -> Result<Acceleration, Self::Error> {
+    MPU6886::get_accel_data(self)
+  }
+
+  fn get_gyro_data(&mut self) -> Result<AngularRate, Self::Error> {
+    MPU6886::get_gyro_data(self)
+  }
+
+  fn get_temp_data(&mut self) -> Result<f32, Self::Error> {
+    MPU6886::get_temp_data(self)
+  }
+
+  fn set_accel_range(&mut self, range: AccelRange) -> Result<(), Self::Error> {
+    let scale = match range {
+      AccelRange::G2 => mpu6886::Ascale::Afs2g,
+      AccelRange::G4 => mpu6886::Ascale::Afs4g,
+      AccelRange::G8 => mpu6886::Ascale::Afs8g,
+      AccelRange::G16 => mpu6886::Ascale::Afs16g,
+    };
+    self.set_accel_fsr(scale)
+  }
+
+  fn set_gyro_range(&mut self, range: GyroRange) -> Result<(), Self::Error> {
+    let scale = match range {
+      GyroRange::Dps125 => return Err(mpu6886::Error::InvalidArgument),
+      GyroRange::Dps250 => mpu6886::Gscale::Gfs250dps,
+      GyroRange::Dps500 => mpu6886::Gscale::Gfs500dps,
+      GyroRange::Dps1000 => mpu6886::Gscale::Gfs1000dps,
+      GyroRange::Dps2000 => mpu6886::Gscale::Gfs2000dps,
+    };
+    self.set_gyro_fsr(scale)
+  }
+
+  fn get_motion_data(&mut self) -> Result<MotionSample, Self::Error> {
+    MPU6886::get_motion_data(self)
+  }
+}
+
+impl<I2C> Imu for SH200Q<I2C>
+where
+  I2C: embedded_hal::i2c::I2c,
+{
+  type Error = sh200q::Error<I2C::Error>;
+
+  fn init<D: DelayUs>(&mut self, delay: &mut D) -> Result<(), Self::Error> {
+    SH200Q::init(self, delay)
+  }
+
+  fn get_accel_data(&mut self) -> Result<Acceleration, Self::Error> {
+    SH200Q::get_accel_data(self)
+  }
+
+  fn get_gyro_data(&mut self) -> Result<AngularRate, Self::Error> {
+    SH200Q::get_gyro_data(self)
+  }
+
+  fn get_temp_data(&mut self) -> Result<f32, Self::Error> {
+    SH200Q::get_temp_data(self)
+  }
+
+  fn set_accel_range(&mut self, range: AccelRange) -> Result<(), Self::Error> {
+    let scale = match range {
+      AccelRange::G2 => return Err(sh200q::Error::InvalidArgument),
+      AccelRange::G4 => sh200q::Ascale::Afs4g,
+      AccelRange::G8 => sh200q::Ascale::Afs8g,
+      AccelRange::G16 => sh200q::Ascale::Afs16g,
+    };
+    self.set_accel_fsr(scale)
+  }
+
+  fn set_gyro_range(&mut self, range: GyroRange) -> Result<(), Self::Error> {
+    let scale = match range {
+      GyroRange::Dps125 => sh200q::Gscale::Gfs125dps,
+      GyroRange::Dps250 => sh200q::Gscale::Gfs250dps,
+      GyroRange::Dps500 => sh200q::Gscale::Gfs500dps,
+      GyroRange::Dps1000 => sh200q::Gscale::Gfs1000dps,
+      GyroRange::Dps2000 => sh200q::Gscale::Gfs2000dps,
+    };
+    self.set_gyro_fsr(scale)
+  }
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+  Mpu6886(mpu6886::Error<E>),
+  Sh200q(sh200q::Error<E>),
+}
+
+impl<E> From<mpu6886::Error<E>> for Error<E> {
+  fn from(error: mpu6886::Error<E>) -> Self {
+    Error::Mpu6886(error)
+  }
+}
+
+impl<E> From<sh200q::Error<E>> for Error<E> {
+  fn from(error: sh200q::Error<E>) -> Self {
+    Error::Sh200q(error)
+  }
+}
+
+/// Whichever IMU the board is fitted with.
+pub enum AnyImu<I2C> {
+  Mpu6886(MPU6886<I2C>),
+  Sh200q(SH200Q<I2C>),
+}
+
+impl<I2C> AnyImu<I2C>
+where
+  I2C: embedded_hal::i2c::I2c,
+{
+  /// Look for an MPU6886 and then for an SH200Q on the bus.
+  ///
+  /// Falls back to the MPU6886 if neither answers, so `init` reports the problem.
+  pub fn probe(mut i2c: I2C) -> Self {
+    let mut id = [0x00u8];
+    let found = |i2c: &mut I2C, address, reg, expected, id: &mut [u8; 1]| {
+      i2c.write_read(address, &[reg], id).is_ok() && id[0] == expected
+    };
+
+    if found(
+      &mut i2c,
+      mpu6886::MPU6886_ADDRESS,
+      mpu6886::MPU6886_WHOAMI,
+      mpu6886::MPU6886_ID,
+      &mut id,
+    ) {
+      AnyImu::Mpu6886(MPU6886::new(i2c))
+    } else if found(
+      &mut i2c,
+      sh200q::SH200Q_ADDRESS,
+      sh200q::SH200Q_WHOAMI,
+      sh200q::SH200Q_ID,
+      &mut id,
+    ) {
+      AnyImu::Sh200q(SH200Q::new(i2c))
+    } else {
+      AnyImu::Mpu6886(MPU6886::new(i2c))
+    }
+  }
+
+  /// The MPU6886, for functionality only it has, e.g. the FIFO.
+  pub fn as_mpu6886(&mut self) -> Option<&mut MPU6886<I2C>> {
+    match self {
+      AnyImu::Mpu6886(imu) => Some(imu),
+      AnyImu::Sh200q(_) => None,
+    }
+  }
+
+  pub fn as_sh200q(&mut self) -> Option<&mut SH200Q<I2C>> {
+    match self {
+      AnyImu::Mpu6886(_) => None,
+      AnyImu::Sh200q(imu) => Some(imu),
+    }
+  }
+}
+
+impl<I2C> Imu for AnyImu<I2C>
+where
+  I2C: embedded_hal::i2c::I2c,
+{
+  type Error = Error<I2C::Error>;
+
+  fn init<D: DelayUs>(&mut self, delay: &mut D) -> Result<(), Self::Error> {
+    match self {
+      AnyImu::Mpu6886(imu) => Ok(Imu::init(imu, delay)?),
+      AnyImu::Sh200q(imu) => Ok(Imu::init(imu, delay)?),
+    }
+  }
+
+  fn get_accel_data(&mut self) -> Result<Acceleration, Self::Error> {
+    match self {
+      AnyImu::Mpu6886(imu) => Ok(Imu::get_accel_data(imu)?),
+      AnyImu::Sh200q(imu) => Ok(Imu::get_accel_data(imu)?),
+    }
+  }
+
+  fn get_gyro_data(&mut self) -> Result<AngularRate, Self::Error> {
+    match self {
+      AnyImu::Mpu6886(imu) => Ok(Imu::get_gyro_data(imu)?),
+      AnyImu::Sh200q(imu) => Ok(Imu::get_gyro_data(imu)?),
+    }
+  }
+
+  fn get_temp_data(&mut self) -> Result<f32, Self::Error> {
+    match self {
+      AnyImu::Mpu6886(imu) => Ok(Imu::get_temp_data(imu)?),
+      AnyImu::Sh200q(imu) => Ok(Imu::get_temp_data(imu)?),
+    }
+  }
+
+  fn set_accel_range(&mut self, range: AccelRange) -> Result<(), Self::Error> {
+    match self {
+      AnyImu::Mpu6886(imu) => Ok(imu.set_accel_range(range)?),
+      AnyImu::Sh200q(imu) => Ok(imu.set_accel_range(range)?),
+    }
+  }
+
+  fn set_gyro_range(&mut self, range: GyroRange) -> Result<(), Self::Error> {
+    match self {
+      AnyImu::Mpu6886(imu) => Ok(imu.set_gyro_range(range)?),
+      AnyImu::Sh200q(imu) => Ok(imu.set_gyro_range(range)?),
+    }
+  }
+
+  fn get_motion_data(&mut self) -> Result<MotionSample, Self::Error> {
+    match self {
+      AnyImu::Mpu6886(imu) => Ok(Imu::get_motion_data(imu)?),
+      AnyImu::Sh200q(imu) => Ok(Imu::get_motion_data(imu)?),
+    }
+  }
+}
@@ -0,0 +1,388 @@
+use core::cell::RefCell;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use critical_section::Mutex;
+use display_interface::DataFormat;
+use display_interface::DisplayError;
+use display_interface::WriteOnlyDataCommand as _;
+use display_interface_spi::SPIInterfaceNoCS;
+use embedded_graphics::geometry::OriginDimensions;
+use embedded_graphics::pixelcolor::Rgb565;
+use esp_idf_hal::gpio::*;
+use esp_idf_hal::i2c::I2cConfig;
+use esp_idf_hal::i2c::I2cDriver;
+use esp_idf_hal::i2c::I2cError;
+use esp_idf_hal::i2c::I2C1;
+use esp_idf_hal::prelude::*;
+use esp_idf_hal::spi;
+
+use anyhow::Result;
+use esp_idf_hal::spi::SPI3;
+use esp_idf_hal::spi::{SpiDeviceDriver, SpiDriver, SpiDriverConfig};
+use esp_idf_sys::{esp, EspError};
+
+use embedded_hal_bus::i2c;
+use mipidsi::{Builder, ColorInversion};
+
+use crate::display_buffer::DisplayBuffer;
+use crate::time::{Clock, SystemClock};
+use crate::{axp192, button, imu};
+
+#[cfg(not(feature = "m5stickc_plus"))]
+type Display<'a> = mipidsi::Display<
+  SPIInterfaceNoCS<SpiDeviceDriver<'a, SpiDriver<'a>>, PinDriver<'a, Gpio23, Output>>,
+  mipidsi::models::ST7735s,
+  PinDriver<'a, Gpio18, Output>,
+>;
+
+#[cfg(not(feature = "m5stickc_plus"))]
+const SPI_BAUDRATE: u32 = 27;
+
+#[cfg(feature = "m5stickc_plus")]
+type Display<'a> = mipidsi::Display<
+  SPIInterfaceNoCS<SpiDeviceDriver<'a, SpiDriver<'a>>, PinDriver<'a, Gpio23, Output>>,
+  mipidsi::models::ST7789,
+  PinDriver<'a, Gpio18, Output>,
+>;
+#[cfg(feature = "m5stickc_plus")]
+const SPI_BAUDRATE: u32 = 40;
+
+pub macro new_m5($peripherals:ident) {
+  m5stickc::M5::new(M5Peripherals {
+    i2c1: $peripherals.i2c1,
+    spi3: $peripherals.spi3,
+    gpio5: $peripherals.pins.gpio5,
+    gpio10: $peripherals.pins.gpio10,
+    gpio13: $peripherals.pins.gpio13,
+    gpio15: $peripherals.pins.gpio15,
+    gpio18: $peripherals.pins.gpio18,
+    gpio21: $peripherals.pins.gpio21,
+    gpio22: $peripherals.pins.gpio22,
+    gpio23: $peripherals.pins.gpio23,
+    gpio35: $peripherals.pins.gpio35,
+    gpio37: $peripherals.pins.gpio37,
+    gpio39: $peripherals.pins.gpio39,
+  })
+}
+
+pub struct M5Peripherals {
+  // i2c1
+  pub i2c1: I2C1,
+  pub gpio21: Gpio21,
+  pub gpio22: Gpio22,
+
+  // spi3
+  pub spi3: SPI3,
+  pub gpio15: Gpio15,
+  pub gpio13: Gpio13,
+  pub gpio23: Gpio23,
+  pub gpio5: Gpio5,
+  pub gpio18: Gpio18,
+
+  // LED
+  pub gpio10: Gpio10,
+
+  // IMU interrupt
+  pub gpio35: Gpio35,
+
+  // Button A
+  pub gpio37: Gpio37,
+  // Button B
+  pub gpio39: Gpio39,
+}
+
+pub struct M5<'a> {
+  i2c1: Box<Mutex<RefCell<I2cDriver<'a>>>>,
+  axp: axp192::Axp192<i2c::CriticalSectionDevice<'a, I2cDriver<'a>>>,
+  imu: imu::AnyImu<i2c::CriticalSectionDevice<'a, I2cDriver<'a>>>,
+  imu_int: PinDriver<'a, Gpio35, Input>,
+  btn_a: button::Button<PinDriver<'a, Gpio37, Input>>,
+  btn_b: button::Button<PinDriver<'a, Gpio39, Input>>,
+  btn_pwr: button::PowerButton,
+  /// Buttons A and B are read from edge queues, see `enable_button_interrupts`.
+  button_interrupts: bool,
+  /// Events of the last `update_power`s other than the power key, see `drain_axp_events`.
+  axp_events: axp192::Axp192Events,
+  lcd: Display<'a>,
+  led: PinDriver<'a, Gpio10, Output>,
+}
+
+impl<'a> M5<'a> {
+  pub fn new(peripherals: M5Peripherals) -> Result<Self, EspError> {
+    let config = I2cConfig::new().baudrate(400.kHz().into());
+    let i2c1 = I2cDriver::new(
+      peripherals.i2c1,
+      peripherals.gpio21,
+      peripherals.gpio22,
+      &config,
+    )?;
+    let i2c1 = Box::new(Mutex::new(RefCell::new(i2c1)));
+
+    let i2c1_ref = unsafe { crate::misc::extend_lifetime(i2c1.as_ref()) };
+
+    let axp = axp192::Axp192::new(i2c::CriticalSectionDevice::new(i2c1_ref)).map_err(axp_error)?;
+    let imu = imu::AnyImu::probe(i2c::CriticalSectionDevice::new(i2c1_ref));
+    let imu_int = PinDriver::input(peripherals.gpio35)?;
+
+    let pin_a = PinDriver::input(peripherals.gpio37)?;
+    let btn_a = button::Button::new(pin_a, true, 10);
+    let pin_b = PinDriver::input(peripherals.gpio39)?;
+    let btn_b = button::Button::new(pin_b, true, 10);
+
+    let spi = peripherals.spi3;
+    let tft_mosi = peripherals.gpio15;
+    let tft_sclk = peripherals.gpio13;
+    let tft_dc = PinDriver::output(peripherals.gpio23)?;
+    let tft_cs = peripherals.gpio5;
+    let tft_rst = PinDriver::output(peripherals.gpio18)?;
+
+    let config = spi::config::Config::default()
+      .baudrate(SPI_BAUDRATE.MHz().into())
+      .write_only(true);
+    let spi = SpiDeviceDriver::new_single(
+      spi,
+      tft_sclk,
+      tft_mosi,
+      None::<Gpio0>,
+      Some(tft_cs),
+      &SpiDriverConfig::new().dma(spi::Dma::Auto(1024)),
+      &config,
+    )?;
+
+    let mut delay = esp_idf_hal::delay::Ets {};
+    let di = SPIInterfaceNoCS::new(spi, tft_dc);
+    #[cfg(not(feature = "m5stickc_plus"))]
+    let display = Builder::st7735s(di)
+      .with_invert_colors(ColorInversion::Inverted)
+      .with_color_order(mipidsi::ColorOrder::Bgr)
+      .with_orientation(mipidsi::Orientation::Landscape(true))
+      .with_window_offset_handler(|_| (1, 26))
+      .init(&mut delay, Some(tft_rst))
+      .unwrap();
+
+    #[cfg(feature = "m5stickc_plus")]
+    let display = Builder::st7789(di)
+      .with_invert_colors(ColorInversion::Inverted)
+      .with_color_order(mipidsi::ColorOrder::Bgr)
+      .init(&mut delay, Some(tft_rst))
+      .unwrap();
+
+    let mut led = PinDriver::output(peripherals.gpio10)?;
+    let _ = led.set_high();
+
+    Ok(Self {
+      i2c1,
+      axp,
+      imu,
+      imu_int,
+      btn_a,
+      btn_b,
+      btn_pwr: button::PowerButton::new(),
+      button_interrupts: false,
+      axp_events: axp192::Axp192Events::empty(),
+      lcd: display,
+      led,
+    })
+  }
+
+  pub fn i2c1(&self) -> &Mutex<RefCell<I2cDriver<'a>>> {
+    self.i2c1.as_ref()
+  }
+
+  pub fn axp(&mut self) -> &mut axp192::Axp192<i2c::CriticalSectionDevice<'a, I2cDriver<'a>>> {
+    &mut self.axp
+  }
+
+  pub fn imu(&mut self) -> &mut imu::AnyImu<i2c::CriticalSectionDevice<'a, I2cDriver<'a>>> {
+    &mut self.imu
+  }
+
+  /// INT pin of the IMU, high while an enabled interrupt is pending.
+  pub fn imu_int(&mut self) -> &mut PinDriver<'a, Gpio35, Input> {
+    &mut self.imu_int
+  }
+
+  /// Call `callback` from the GPIO ISR on the rising edge of the IMU INT pin.
+  ///
+  /// The interrupt is disabled after every edge, call `enable_imu_interrupt` again once the event
+  /// has been handled. On an MPU6886, INT stays high until `MPU6886::get_interrupt_status` is read.
+  ///
+  /// # Safety
+  ///
+  /// `callback` runs in interrupt context, see `PinDriver::subscribe`.
+  pub unsafe fn subscribe_imu_interrupt<F>(&mut self, callback: F) -> Result<(), EspError>
+  where
+    F: FnMut() + Send + 'static,
+  {
+    self.imu_int.set_interrupt_type(InterruptType::PosEdge)?;
+    self.imu_int.subscribe(callback)?;
+    self.imu_int.enable_interrupt()
+  }
+
+  pub fn enable_imu_interrupt(&mut self) -> Result<(), EspError> {
+    self.imu_int.enable_interrupt()
+  }
+
+  pub fn unsubscribe_imu_interrupt(&mut self) -> Result<(), EspError> {
+    self.imu_int.unsubscribe()
+  }
+
+  /// Wake up from light or deep sleep when the IMU raises INT, e.g. on motion.
+  pub fn enable_imu_wakeup(&mut self) -> Result<(), EspError> {
+    esp!(unsafe { esp_idf_sys::esp_sleep_enable_ext0_wakeup(self.imu_int.pin(), 1) })
+  }
+
+  /// Timestamp the edges of buttons A and B in the GPIO ISR instead of polling the pins, so
+  /// presses shorter than the time between two `update` calls are not lost.
+  ///
+  /// `update` still has to be called; it debounces the queued edges and reports one state change
+  /// of each button per call. The GPIO driver disables the interrupt on every edge and `update`
+  /// enables it again, so only the first edge between two calls is queued.
+  pub fn enable_button_interrupts(&mut self) -> Result<(), EspError> {
+    subscribe_button(&mut self.btn_a)?;
+    subscribe_button(&mut self.btn_b)?;
+    self.button_interrupts = true;
+    Ok(())
+  }
+
+  /// Go back to polling the buttons in `update`.
+  pub fn disable_button_interrupts(&mut self) -> Result<(), EspError> {
+    self.btn_a.pin_mut().unsubscribe()?;
+    self.btn_a.set_edge_queue(None);
+    self.btn_b.pin_mut().unsubscribe()?;
+    self.btn_b.set_edge_queue(None);
+    self.button_interrupts = false;
+    Ok(())
+  }
+
+  pub fn btn_a(&self) -> &button::Button<PinDriver<'a, Gpio37, Input>> {
+    &self.btn_a
+  }
+
+  pub fn btn_b(&self) -> &button::Button<PinDriver<'a, Gpio39, Input>> {
+    &self.btn_b
+  }
+
+  /// Power key, reported as `ButtonEvent::Click` and `ButtonEvent::LongPress`.
+  ///
+  /// Only updated by `update_power`.
+  pub fn btn_pwr(&self) -> &button::PowerButton {
+    &self.btn_pwr
+  }
+
+  /// Buttons A, B and the power key, as of the last `update` and `update_power`.
+  pub fn buttons(&self) -> [&dyn button::PushButton; 3] {
+    [&self.btn_a, &self.btn_b, &self.btn_pwr]
+  }
+
+  /// AXP192 events collected by `update_power` since the last call, without the power key
+  /// presses.
+  ///
+  /// `update_power` clears the IRQ status of the AXP192 to read the power key, so once it is used,
+  /// use this instead of `Axp192::drain_events` on `axp`, which would take the presses away from
+  /// `btn_pwr`.
+  pub fn drain_axp_events(&mut self) -> axp192::Axp192Events {
+    core::mem::take(&mut self.axp_events)
+  }
+
+  pub fn lcd(&mut self) -> &mut Display<'a> {
+    &mut self.lcd
+  }
+
+  pub fn draw(&mut self, display_buffer: &DisplayBuffer<Rgb565>) -> Result<(), DisplayError> {
+    let size = self.lcd.size();
+
+    unsafe {
+      let dcs = self.lcd.dcs();
+
+      let (sx, sy, ex, ey) = (
+        1,
+        26,
+        (size.width as u16) - 1 + 1,
+        (size.height as u16) - 1 + 26,
+      );
+      dcs.write_command(mipidsi::dcs::SetColumnAddress::new(sx, ex))?;
+      dcs.write_command(mipidsi::dcs::SetPageAddress::new(sy, ey))?;
+
+      dcs.write_command(mipidsi::dcs::WriteMemoryStart)?;
+      dcs.di.send_data(DataFormat::U8(display_buffer.as_bytes()))
+
+      // self.lcd.set_pixels(
+      //   0,
+      //   0,
+      //   (size.width as u16) - 1,
+      //   (size.height as u16) - 1,
+      //   display_buffer.buffer.iter().copied(),
+      // )
+    }
+  }
+
+  pub fn led(&mut self) -> &mut PinDriver<'a, Gpio10, Output> {
+    &mut self.led
+  }
+
+  /// Read buttons A and B.
+  pub fn update(&mut self) {
+    self.btn_a.read();
+    self.btn_b.read();
+    self.rearm_button_interrupts();
+  }
+
+  /// Read the power key into `btn_pwr` and collect the other AXP192 events for
+  /// `drain_axp_events`.
+  ///
+  /// Reads and clears all IRQ status registers of the AXP192, so call it at the rate the power key
+  /// needs rather than every frame. `Axp192::get_btn_press` no longer sees any presses once this
+  /// is used.
+  pub fn update_power(&mut self) -> Result<(), EspError> {
+    let events = self.axp.drain_events().map_err(axp_error)?;
+    self.btn_pwr.read(&events);
+    for event in events.iter() {
+      if !matches!(
+        event,
+        axp192::Axp192Event::PekShortPress | axp192::Axp192Event::PekLongPress
+      ) {
+        self.axp_events.insert(event);
+      }
+    }
+    Ok(())
+  }
+
+  /// Enable the button interrupts again after the driver disabled them on an edge.
+  fn rearm_button_interrupts(&mut self) {
+    if self.button_interrupts {
+      // Only fails for pins without interrupt support, which buttons A and B are not
+      let _ = self.btn_a.pin_mut().enable_interrupt();
+      let _ = self.btn_b.pin_mut().enable_interrupt();
+    }
+  }
+}
+
+/// The closest `EspError` to an AXP192 error.
+fn axp_error(error: axp192::Error<I2cError>) -> EspError {
+  let code = match error {
+    axp192::Error::Bus(error) => return error.cause(),
+    axp192::Error::InvalidArgument => esp_idf_sys::ESP_ERR_INVALID_ARG,
+    axp192::Error::UnexpectedDeviceId { .. } => esp_idf_sys::ESP_ERR_INVALID_RESPONSE,
+    axp192::Error::NotInitialized => esp_idf_sys::ESP_ERR_INVALID_STATE,
+  };
+  EspError::from(code as esp_idf_sys::esp_err_t).unwrap()
+}
+
+fn subscribe_button<P: InputPin>(
+  button: &mut button::Button<PinDriver<'_, P, Input>>,
+) -> Result<(), EspError> {
+  let queue = Arc::new(button::EdgeQueue::new());
+  let isr_queue = queue.clone();
+  let pin = button.pin_mut();
+  pin.set_interrupt_type(InterruptType::AnyEdge)?;
+  unsafe {
+    // Only the time is taken here: `esp_timer_get_time` is in IRAM and safe to call from an ISR.
+    // The pin level is read and the interrupt enabled again by `update`, in task context.
+    pin.subscribe(move || isr_queue.push(SystemClock.now()))?;
+  }
+  pin.enable_interrupt()?;
+  button.set_edge_queue(Some(queue));
+  Ok(())
+}
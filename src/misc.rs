@@ -1,7 +1,10 @@
 use core::mem;
 
+/// `SystemClock` in milliseconds, wrapping after 49 days.
+#[cfg(any(feature = "esp_idf", feature = "std"))]
 pub fn millis() -> u32 {
-  unsafe { (esp_idf_sys::esp_timer_get_time() / 1000) as u32 }
+  use crate::time::{Clock, SystemClock};
+  SystemClock.now().as_millis() as u32
 }
 
 pub fn map(x: i64, in_min: i64, in_max: i64, out_min: i64, out_max: i64) -> i64 {
@@ -32,3 +35,23 @@ pub fn enable_core0_wdt() {
 pub fn disable_core0_wdt() {
   todo!()
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn millis_counts_on_the_host() {
+    let start = millis();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    assert!(millis() - start >= 20);
+  }
+
+  #[test]
+  fn map_scales_linearly() {
+    assert_eq!(map(50, 0, 100, 0, 1000), 500);
+    assert_eq!(map(0, -10, 10, 100, 200), 150);
+    assert_eq!(map(3, 0, 4, 8, 0), 2);
+  }
+}
@@ -1,23 +1,77 @@
 use embedded_hal::delay::DelayUs;
+use libm::powf;
 
-use esp_idf_hal::i2c::I2cError;
+use crate::calibration::Calibration;
+use crate::vector::{Acceleration, AngularRate, Vector3};
 
-const MPU6886_ADDRESS: u8 = 0x68;
-const MPU6886_WHOAMI: u8 = 0x75;
+pub(crate) const MPU6886_ADDRESS: u8 = 0x68;
+const MPU6886_SELF_TEST_X_GYRO: u8 = 0x00;
+const MPU6886_SELF_TEST_X_ACCEL: u8 = 0x0D;
+const MPU6886_XG_OFFS_USRH: u8 = 0x13;
+pub(crate) const MPU6886_WHOAMI: u8 = 0x75;
 const MPU6886_SMPLRT_DIV: u8 = 0x19;
 const MPU6886_CONFIG: u8 = 0x1A;
 const MPU6886_GYRO_CONFIG: u8 = 0x1B;
 const MPU6886_ACCEL_CONFIG: u8 = 0x1C;
 const MPU6886_ACCEL_CONFIG2: u8 = 0x1D;
+const MPU6886_LP_MODE_CFG: u8 = 0x1E;
+const MPU6886_ACCEL_WOM_X_THR: u8 = 0x20;
+const MPU6886_ACCEL_WOM_Y_THR: u8 = 0x21;
+const MPU6886_ACCEL_WOM_Z_THR: u8 = 0x22;
 const MPU6886_FIFO_EN: u8 = 0x23;
 const MPU6886_INT_PIN_CFG: u8 = 0x37;
 const MPU6886_INT_ENABLE: u8 = 0x38;
+const MPU6886_INT_STATUS: u8 = 0x3A;
+const MPU6886_ACCEL_INTEL_CTRL: u8 = 0x69;
 const MPU6886_USER_CTRL: u8 = 0x6A;
 const MPU6886_PWR_MGMT_1: u8 = 0x6B;
+const MPU6886_PWR_MGMT_2: u8 = 0x6C;
 const MPU6886_ACCEL_XOUT_H: u8 = 0x3B;
+const MPU6886_TEMP_OUT_H: u8 = 0x41;
 const MPU6886_GYRO_XOUT_H: u8 = 0x43;
+const MPU6886_FIFO_COUNTH: u8 = 0x72;
+const MPU6886_FIFO_R_W: u8 = 0x74;
+const MPU6886_XA_OFFSET_H: u8 = 0x77;
 
-#[derive(Clone, Copy, Debug)]
+/// Size of the FIFO in bytes.
+const MPU6886_FIFO_SIZE: u16 = 1024;
+
+/// INT_ENABLE bits.
+const INT_WOM: u8 = 0b1110_0000;
+const INT_DATA_READY: u8 = 0b0000_0001;
+
+/// Value of the WHO_AM_I register of an MPU6886.
+pub(crate) const MPU6886_ID: u8 = 0x19;
+
+#[derive(Debug)]
+pub enum Error<E> {
+  /// I2C bus error
+  Bus(E),
+  /// An argument was outside of the range supported by the MPU6886
+  InvalidArgument,
+  /// WHO_AM_I did not identify an MPU6886
+  UnexpectedDeviceId { found: u8 },
+  /// Data was requested before `MPU6886::init`
+  NotInitialized,
+  /// The FIFO filled up and samples were lost, it has been reset
+  FifoOverflow,
+  /// The FIFO was read without being enabled with `MPU6886::enable_fifo`
+  FifoDisabled,
+  /// The sensor moved while measuring a calibration
+  NotStationary,
+  /// Data was requested while the sensor is in `PowerMode::Sleep`
+  Sleeping,
+  /// Gyroscope data was requested in a power mode that turns the gyroscope off
+  GyroDisabled,
+}
+
+impl<E> From<E> for Error<E> {
+  fn from(error: E) -> Self {
+    Error::Bus(error)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Ascale {
   Afs2g = 0,
   Afs4g = 1,
@@ -25,7 +79,7 @@ pub enum Ascale {
   Afs16g = 3,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Gscale {
   Gfs250dps = 0,
   Gfs500dps = 1,
@@ -33,115 +87,441 @@ pub enum Gscale {
   Gfs2000dps = 3,
 }
 
+/// Bandwidth of the gyroscope (and temperature) low pass filter.
+///
+/// `Hz250` and `Hz3281` run the gyroscope at 8kHz, all others at 1kHz.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GyroDlpf {
+  Hz250 = 0,
+  Hz176 = 1,
+  Hz92 = 2,
+  Hz41 = 3,
+  Hz20 = 4,
+  Hz10 = 5,
+  Hz5 = 6,
+  Hz3281 = 7,
+}
+
+/// Bandwidth of the accelerometer low pass filter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccelDlpf {
+  Hz218 = 0,
+  Hz99 = 2,
+  Hz45 = 3,
+  Hz21 = 4,
+  Hz10 = 5,
+  Hz5 = 6,
+  Hz420 = 7,
+  /// Filter bypassed, 1046Hz bandwidth at a 4kHz rate.
+  Bypass = 8,
+}
+
+/// Number of samples averaged by the accelerometer in low power mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccelAveraging {
+  Samples4 = 0,
+  Samples8 = 1,
+  Samples16 = 2,
+  Samples32 = 3,
+}
+
+/// Power state of the MPU6886, see `MPU6886::set_power_mode`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PowerMode {
+  /// Accelerometer and gyroscope running.
+  #[default]
+  Active,
+  /// Gyroscope drive kept running but its outputs off, waking up faster than from `Sleep`.
+  GyroStandby,
+  /// Only the accelerometer, waking up for every sample and averaging
+  /// `Mpu6886Config::accel_averaging` readings. The output data rate is 1kHz divided by
+  /// `1 + sample_rate_divider`. Combined with wake-on-motion this draws the least current while
+  /// still watching for movement.
+  AccelLowPower,
+  /// Everything off, the registers keep their values.
+  Sleep,
+}
+
+/// Settings applied by `MPU6886::init_with`.
+///
+/// The default is the configuration `MPU6886::init` has always used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mpu6886Config {
+  pub gyro_dlpf: GyroDlpf,
+  pub accel_dlpf: AccelDlpf,
+  /// The output rate is 1kHz divided by `1 + sample_rate_divider`. Ignored with
+  /// `GyroDlpf::Hz250` and `GyroDlpf::Hz3281`, which always output at 8kHz.
+  pub sample_rate_divider: u8,
+  pub gyro_range: Gscale,
+  pub accel_range: Ascale,
+  pub accel_averaging: AccelAveraging,
+}
+
+impl Mpu6886Config {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn gyro_dlpf(mut self, dlpf: GyroDlpf) -> Self {
+    self.gyro_dlpf = dlpf;
+    self
+  }
+
+  pub fn accel_dlpf(mut self, dlpf: AccelDlpf) -> Self {
+    self.accel_dlpf = dlpf;
+    self
+  }
+
+  pub fn sample_rate_divider(mut self, divider: u8) -> Self {
+    self.sample_rate_divider = divider;
+    self
+  }
+
+  pub fn gyro_range(mut self, range: Gscale) -> Self {
+    self.gyro_range = range;
+    self
+  }
+
+  pub fn accel_range(mut self, range: Ascale) -> Self {
+    self.accel_range = range;
+    self
+  }
+
+  pub fn accel_averaging(mut self, averaging: AccelAveraging) -> Self {
+    self.accel_averaging = averaging;
+    self
+  }
+
+  /// Output data rate in Hz. The accelerometer runs at 4kHz instead with `AccelDlpf::Bypass`.
+  pub fn sample_rate_hz(&self) -> f32 {
+    1_000_000.0 / self.sample_period_us() as f32
+  }
+
+  fn sample_period_us(&self) -> u64 {
+    match self.gyro_dlpf {
+      // SMPLRT_DIV only applies to the 1kHz internal rate
+      GyroDlpf::Hz250 | GyroDlpf::Hz3281 => 125,
+      _ => 1000 * (1 + self.sample_rate_divider as u64),
+    }
+  }
+
+  /// Interval of the FIFO packets. The FIFO is written whenever the fastest of `sensors` has a
+  /// new sample.
+  fn fifo_period_us(&self, sensors: FifoSensors) -> u64 {
+    let accel = match self.accel_dlpf {
+      AccelDlpf::Bypass => 250,
+      _ => self.sample_period_us(),
+    };
+    match sensors {
+      FifoSensors::Accel => accel,
+      FifoSensors::Gyro => self.sample_period_us(),
+      FifoSensors::AccelGyro => accel.min(self.sample_period_us()),
+    }
+  }
+
+  fn accel_config2(&self) -> u8 {
+    let dlpf = match self.accel_dlpf {
+      AccelDlpf::Bypass => 0x08,
+      dlpf => dlpf as u8,
+    };
+    ((self.accel_averaging as u8) << 4) | dlpf
+  }
+}
+
+impl Default for Mpu6886Config {
+  fn default() -> Self {
+    Self {
+      gyro_dlpf: GyroDlpf::Hz176,
+      accel_dlpf: AccelDlpf::Hz218,
+      sample_rate_divider: 5,
+      gyro_range: Gscale::Gfs2000dps,
+      accel_range: Ascale::Afs8g,
+      accel_averaging: AccelAveraging::Samples4,
+    }
+  }
+}
+
+/// Sensors written to the FIFO. The temperature is always included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FifoSensors {
+  Accel,
+  Gyro,
+  AccelGyro,
+}
+
+impl FifoSensors {
+  /// Bytes per FIFO packet.
+  pub fn packet_size(self) -> usize {
+    match self {
+      FifoSensors::Accel | FifoSensors::Gyro => 8,
+      FifoSensors::AccelGyro => 14,
+    }
+  }
+
+  fn fifo_en(self) -> u8 {
+    match self {
+      FifoSensors::Accel => 1 << 3,
+      FifoSensors::Gyro => 1 << 4,
+      FifoSensors::AccelGyro => (1 << 4) | (1 << 3),
+    }
+  }
+}
+
+/// One sample read from the FIFO.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FifoSample {
+  /// Microseconds since the FIFO was enabled, derived from the sample rate.
+  pub timestamp_us: u64,
+  /// `None` if the accelerometer is not written to the FIFO.
+  pub accel: Option<Acceleration>,
+  /// `None` if the gyroscope is not written to the FIFO.
+  pub gyro: Option<AngularRate>,
+  pub temp_c: f32,
+}
+
+/// Accelerometer, gyroscope and die temperature read in one burst, so all values belong to the
+/// same sample.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MotionSample {
+  pub accel: Acceleration,
+  pub gyro: AngularRate,
+  pub temp_c: f32,
+}
+
+/// Pending interrupts, read from INT_STATUS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InterruptStatus(u8);
+
+impl InterruptStatus {
+  /// Acceleration changed by more than the wake-on-motion threshold on any axis.
+  pub fn motion(self) -> bool {
+    self.0 & INT_WOM != 0
+  }
+
+  pub fn motion_x(self) -> bool {
+    self.0 & (1 << 7) != 0
+  }
+
+  pub fn motion_y(self) -> bool {
+    self.0 & (1 << 6) != 0
+  }
+
+  pub fn motion_z(self) -> bool {
+    self.0 & (1 << 5) != 0
+  }
+
+  pub fn fifo_overflow(self) -> bool {
+    self.0 & (1 << 4) != 0
+  }
+
+  /// A new sample is available in the output registers.
+  pub fn data_ready(self) -> bool {
+    self.0 & INT_DATA_READY != 0
+  }
+
+  pub fn bits(self) -> u8 {
+    self.0
+  }
+}
+
+/// Outcome of the self-test of one axis.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SelfTestAxis {
+  /// Change of the output when the self-test was enabled, in g or dps.
+  pub response: f32,
+  /// Response measured at the factory, `None` if the unit has no trim for the axis.
+  pub factory_response: Option<f32>,
+  pub passed: bool,
+}
+
+/// Result of `MPU6886::self_test`, axes in X/Y/Z order.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SelfTestResult {
+  pub accel: [SelfTestAxis; 3],
+  pub gyro: [SelfTestAxis; 3],
+}
+
+impl SelfTestResult {
+  pub fn passed(&self) -> bool {
+    self.accel.iter().chain(&self.gyro).all(|axis| axis.passed)
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AxisHealth {
+  #[default]
+  Ok,
+  /// The output did not change at all, which even a sensor at rest does because of noise.
+  Stuck,
+  /// The output reached the end of the full scale range.
+  Saturated,
+}
+
+/// Result of `MPU6886::check_health`, axes in X/Y/Z order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HealthReport {
+  pub accel: [AxisHealth; 3],
+  pub gyro: [AxisHealth; 3],
+}
+
+impl HealthReport {
+  pub fn is_healthy(&self) -> bool {
+    self
+      .accel
+      .iter()
+      .chain(&self.gyro)
+      .all(|axis| *axis == AxisHealth::Ok)
+  }
+}
+
 pub struct MPU6886<I2C> {
   i2c: I2C,
   g_res: f32,
   a_res: f32,
+  config: Mpu6886Config,
+  initialized: bool,
+  power_mode: PowerMode,
+  fifo: Option<FifoSensors>,
+  fifo_index: u64,
+  int_enable: u8,
+  calibration: Calibration,
+  hardware_offsets: bool,
+  accel_trim: Option<[u16; 3]>,
 }
 
 impl<I2C> MPU6886<I2C>
 where
   I2C: embedded_hal::i2c::I2c,
-  I2cError: From<<I2C as embedded_hal::i2c::ErrorType>::Error>,
 {
   pub fn new(i2c: I2C) -> Self {
     Self {
       i2c,
       g_res: 0.0,
       a_res: 0.0,
+      config: Mpu6886Config::default(),
+      initialized: false,
+      power_mode: PowerMode::Active,
+      fifo: None,
+      fifo_index: 0,
+      int_enable: 0x00,
+      calibration: Calibration::default(),
+      hardware_offsets: false,
+      accel_trim: None,
     }
   }
 
-  pub fn init(&mut self) -> Result<(), esp_idf_hal::i2c::I2cError> {
-    let mut delay = esp_idf_hal::delay::Ets {};
+  pub fn init<D: DelayUs>(&mut self, delay: &mut D) -> Result<(), Error<I2C::Error>> {
+    self.init_with(&Mpu6886Config::default(), delay)
+  }
 
+  pub fn init_with<D: DelayUs>(
+    &mut self,
+    config: &Mpu6886Config,
+    delay: &mut D,
+  ) -> Result<(), Error<I2C::Error>> {
     let mut buf = [0x00u8];
     self
       .i2c
       .write_read(MPU6886_ADDRESS, &[MPU6886_WHOAMI], &mut buf)?;
 
-    if buf[0] != 0x19 {
-      return Err(esp_idf_hal::i2c::I2cError::other(
-        esp_idf_sys::EspError::from(-1).unwrap(),
-      ));
+    if buf[0] != MPU6886_ID {
+      return Err(Error::UnexpectedDeviceId { found: buf[0] });
     }
 
     delay.delay_ms(1);
 
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_PWR_MGMT_1, 0x00])?;
+    self.write_register(MPU6886_PWR_MGMT_1, 0x00)?;
     delay.delay_ms(10);
 
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_PWR_MGMT_1, 0x01 << 7])?;
+    self.write_register(MPU6886_PWR_MGMT_1, 0x01 << 7)?;
     delay.delay_ms(10);
 
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_PWR_MGMT_1, 0x01 << 0])?;
+    self.write_register(MPU6886_PWR_MGMT_1, 0x01 << 0)?;
     delay.delay_ms(10);
 
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_ACCEL_CONFIG, 0x10])?;
-    delay.delay_ms(1);
-
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_GYRO_CONFIG, 0x18])?;
-    delay.delay_ms(1);
-
-    self.i2c.write(MPU6886_ADDRESS, &[MPU6886_CONFIG, 0x01])?;
-    delay.delay_ms(1);
-
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_SMPLRT_DIV, 0x05])?;
-    delay.delay_ms(1);
+    self.write_register(MPU6886_INT_ENABLE, 0x00)?;
+    self.apply_config(config)?;
+    self.write_register(MPU6886_USER_CTRL, 0x00)?;
+    self.write_register(MPU6886_FIFO_EN, 0x00)?;
+    // INT active high, push-pull, held until INT_STATUS is read
+    self.write_register(MPU6886_INT_PIN_CFG, 0x22)?;
+    self.write_register(MPU6886_INT_ENABLE, INT_DATA_READY)?;
+    self.int_enable = INT_DATA_READY;
 
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_INT_ENABLE, 0x00])?;
-    delay.delay_ms(1);
+    // Gyroscope start-up time
+    delay.delay_ms(35);
 
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_ACCEL_CONFIG2, 0x00])?;
-    delay.delay_ms(1);
+    self.config = *config;
+    self.initialized = true;
+    self.power_mode = PowerMode::Active;
+    self.fifo = None;
+    // The reset restored the factory offsets
+    self.hardware_offsets = false;
+    self.accel_trim = None;
+    Ok(())
+  }
 
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_USER_CTRL, 0x00])?;
-    delay.delay_ms(1);
+  /// Settings currently applied to the sensor.
+  pub fn config(&self) -> &Mpu6886Config {
+    &self.config
+  }
 
-    self.i2c.write(MPU6886_ADDRESS, &[MPU6886_FIFO_EN, 0x00])?;
-    delay.delay_ms(1);
+  pub fn power_mode(&self) -> PowerMode {
+    self.power_mode
+  }
 
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_INT_PIN_CFG, 0x22])?;
-    delay.delay_ms(1);
+  /// Switch the sensors on or off to trade readings for current draw.
+  ///
+  /// Reads that need a sensor which is off fail with `Error::Sleeping` or `Error::GyroDisabled`.
+  /// The gyroscope needs about 35ms to settle after coming back from `Sleep` or `AccelLowPower`.
+  pub fn set_power_mode(&mut self, mode: PowerMode) -> Result<(), Error<I2C::Error>> {
+    if !self.initialized {
+      return Err(Error::NotInitialized);
+    }
 
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_INT_ENABLE, 0x01])?;
+    // CLKSEL 1 picks the gyroscope PLL when it runs and the internal oscillator otherwise
+    match mode {
+      PowerMode::Active => {
+        self.write_register(MPU6886_PWR_MGMT_1, 0x01)?;
+        self.write_register(MPU6886_PWR_MGMT_2, 0x00)?;
+        self.write_register(MPU6886_ACCEL_CONFIG2, self.config.accel_config2())?;
+      }
+      PowerMode::GyroStandby => {
+        self.write_register(MPU6886_PWR_MGMT_1, (1 << 4) | 0x01)?;
+        self.write_register(MPU6886_PWR_MGMT_2, 0x00)?;
+        self.write_register(MPU6886_ACCEL_CONFIG2, self.config.accel_config2())?;
+      }
+      PowerMode::AccelLowPower => {
+        // Low power mode needs the accelerometer filter, i.e. ACCEL_FCHOICE_B cleared, and the
+        // gyroscope must not be duty cycled
+        self.write_register(MPU6886_ACCEL_CONFIG2, self.config.accel_config2() & !0x08)?;
+        self.write_register(MPU6886_LP_MODE_CFG, 0x00)?;
+        self.write_register(MPU6886_PWR_MGMT_2, 0x07)?;
+        self.write_register(MPU6886_PWR_MGMT_1, (1 << 5) | 0x01)?;
+      }
+      PowerMode::Sleep => {
+        self.write_register(MPU6886_PWR_MGMT_1, (1 << 6) | 0x01)?;
+      }
+    }
 
-    delay.delay_ms(100);
+    self.power_mode = mode;
+    Ok(())
+  }
 
-    self.set_gyro_fsr(Gscale::Gfs2000dps)?;
-    delay.delay_ms(10);
-    self.set_accel_fsr(Ascale::Afs8g)?;
+  /// Turn all sensors off, same as `set_power_mode(PowerMode::Sleep)`.
+  pub fn sleep(&mut self) -> Result<(), Error<I2C::Error>> {
+    self.set_power_mode(PowerMode::Sleep)
+  }
 
-    Ok(())
+  /// Turn all sensors back on, same as `set_power_mode(PowerMode::Active)`.
+  pub fn wake(&mut self) -> Result<(), Error<I2C::Error>> {
+    self.set_power_mode(PowerMode::Active)
   }
 
-  pub fn set_gyro_fsr(&mut self, scale: Gscale) -> Result<(), esp_idf_hal::i2c::I2cError> {
+  pub fn set_gyro_fsr(&mut self, scale: Gscale) -> Result<(), Error<I2C::Error>> {
     let regdata = (scale as u8) << 3;
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_GYRO_CONFIG, regdata])?;
+    self.write_register(MPU6886_GYRO_CONFIG, regdata)?;
+    self.config.gyro_range = scale;
 
     self.g_res = match scale {
       Gscale::Gfs250dps => 250.0 / 32768.0,
@@ -153,11 +533,10 @@ where
     Ok(())
   }
 
-  pub fn set_accel_fsr(&mut self, scale: Ascale) -> Result<(), esp_idf_hal::i2c::I2cError> {
+  pub fn set_accel_fsr(&mut self, scale: Ascale) -> Result<(), Error<I2C::Error>> {
     let regdata = (scale as u8) << 3;
-    self
-      .i2c
-      .write(MPU6886_ADDRESS, &[MPU6886_ACCEL_CONFIG, regdata])?;
+    self.write_register(MPU6886_ACCEL_CONFIG, regdata)?;
+    self.config.accel_range = scale;
 
     self.a_res = match scale {
       Ascale::Afs2g => 2.0 / 32768.0,
@@ -169,39 +548,657 @@ where
     Ok(())
   }
 
-  pub fn get_gyro_data(&mut self) -> Result<(f32, f32, f32), esp_idf_hal::i2c::I2cError> {
+  pub fn get_gyro_data(&mut self) -> Result<AngularRate, Error<I2C::Error>> {
+    self.check_gyro_running()?;
+
     let mut buf = [0x00u8; 6];
 
     self
       .i2c
       .write_read(MPU6886_ADDRESS, &[MPU6886_GYRO_XOUT_H], &mut buf)?;
 
-    let gx = (((buf[0] as u16) << 8) | (buf[1] as u16)) as i16;
-    let gy = (((buf[2] as u16) << 8) | (buf[3] as u16)) as i16;
-    let gz = (((buf[4] as u16) << 8) | (buf[5] as u16)) as i16;
-
-    Ok((
-      (gx as f32) * self.g_res,
-      (gy as f32) * self.g_res,
-      (gz as f32) * self.g_res,
-    ))
+    Ok(self.correct_gyro(scale_xyz(&buf, self.g_res).into()))
   }
 
-  pub fn get_accel_data(&mut self) -> Result<(f32, f32, f32), esp_idf_hal::i2c::I2cError> {
+  pub fn get_accel_data(&mut self) -> Result<Acceleration, Error<I2C::Error>> {
+    self.check_awake()?;
+
     let mut buf = [0x00u8; 6];
 
     self
       .i2c
       .write_read(MPU6886_ADDRESS, &[MPU6886_ACCEL_XOUT_H], &mut buf)?;
 
-    let gx = (((buf[0] as u16) << 8) | (buf[1] as u16)) as i16;
-    let gy = (((buf[2] as u16) << 8) | (buf[3] as u16)) as i16;
-    let gz = (((buf[4] as u16) << 8) | (buf[5] as u16)) as i16;
+    Ok(self.correct_accel(scale_xyz(&buf, self.a_res).into()))
+  }
+
+  /// Die temperature in degrees Celsius.
+  pub fn get_temp_data(&mut self) -> Result<f32, Error<I2C::Error>> {
+    self.check_awake()?;
+
+    let mut buf = [0x00u8; 2];
+
+    self
+      .i2c
+      .write_read(MPU6886_ADDRESS, &[MPU6886_TEMP_OUT_H], &mut buf)?;
+
+    Ok(temp_from_raw(&buf))
+  }
+
+  /// Read accelerometer, temperature and gyroscope with a single 14 byte transfer.
+  pub fn get_motion_data(&mut self) -> Result<MotionSample, Error<I2C::Error>> {
+    self.check_gyro_running()?;
+    let sample = self.read_motion_uncorrected()?;
+    Ok(MotionSample {
+      accel: self.correct_accel(sample.accel),
+      gyro: self.correct_gyro(sample.gyro),
+      temp_c: sample.temp_c,
+    })
+  }
+
+  /// Corrections applied to all readings.
+  pub fn calibration(&self) -> &Calibration {
+    &self.calibration
+  }
 
-    Ok((
-      (gx as f32) * self.a_res,
-      (gy as f32) * self.a_res,
-      (gz as f32) * self.a_res,
-    ))
+  /// Apply `calibration` to all further readings, e.g. one restored with `Calibration::from_bytes`.
+  ///
+  /// Offsets programmed into the chip with `program_offsets` are updated.
+  pub fn set_calibration(&mut self, calibration: Calibration) -> Result<(), Error<I2C::Error>> {
+    self.calibration = calibration;
+    if self.hardware_offsets {
+      self.program_offsets()?;
+    }
+    Ok(())
+  }
+
+  /// Average `samples` gyroscope readings at the output data rate and use the result as gyroscope
+  /// bias.
+  ///
+  /// The sensor has to lie still, `Error::NotStationary` is returned and the calibration is kept
+  /// if it moved.
+  pub fn calibrate_gyro<D: DelayUs>(
+    &mut self,
+    samples: u16,
+    delay: &mut D,
+  ) -> Result<AngularRate, Error<I2C::Error>> {
+    // Peak to peak gyroscope noise at rest stays well below this
+    const MAX_SPREAD_DPS: f32 = 5.0;
+
+    self.check_gyro_running()?;
+    let bias = self.measure_mean(samples, delay, 0.0, MAX_SPREAD_DPS)?.gyro;
+    let bias = if self.hardware_offsets {
+      bias + self.calibration.gyro_bias
+    } else {
+      bias
+    };
+
+    self.set_calibration(Calibration {
+      gyro_bias: bias,
+      ..self.calibration
+    })?;
+    Ok(bias)
+  }
+
+  /// Average `samples` accelerometer readings at the output data rate, without the calibration
+  /// applied, for `SixPositionCalibration::add`.
+  ///
+  /// The sensor has to lie still, otherwise `Error::NotStationary` is returned.
+  pub fn measure_accel<D: DelayUs>(
+    &mut self,
+    samples: u16,
+    delay: &mut D,
+  ) -> Result<Acceleration, Error<I2C::Error>> {
+    const MAX_SPREAD_G: f32 = 0.1;
+
+    let accel = self.measure_mean(samples, delay, MAX_SPREAD_G, 0.0)?.accel;
+    if self.hardware_offsets {
+      Ok(accel + self.calibration.accel_offset)
+    } else {
+      Ok(accel)
+    }
+  }
+
+  /// Move the gyroscope bias and accelerometer offset of the calibration into the chip's offset
+  /// registers, so the sensor outputs and the FIFO are corrected as well.
+  ///
+  /// The accelerometer scale is still applied by the driver. The offsets are lost on `init`, call
+  /// this again afterwards.
+  pub fn program_offsets(&mut self) -> Result<(), Error<I2C::Error>> {
+    if !self.initialized {
+      return Err(Error::NotInitialized);
+    }
+
+    let trim = match self.accel_trim {
+      Some(trim) => trim,
+      None => {
+        let mut buf = [0x00u8; 8];
+        self
+          .i2c
+          .write_read(MPU6886_ADDRESS, &[MPU6886_XA_OFFSET_H], &mut buf)?;
+        let trim = [0, 3, 6].map(|at| u16::from_be_bytes([buf[at], buf[at + 1]]));
+        self.accel_trim = Some(trim);
+        trim
+      }
+    };
+
+    // Gyroscope offsets are in LSB of the ±1000dps range, accelerometer offsets in 0.98mg
+    let bias: [f32; 3] = self.calibration.gyro_bias.vector().into();
+    let gyro = bias.map(|bias| round_to_i16(-bias * 32.8));
+    let offset: [f32; 3] = self.calibration.accel_offset.vector().into();
+    let mut accel = trim;
+    for (value, offset) in accel.iter_mut().zip(offset) {
+      // 15 bit value, bit 0 is reserved
+      let factory = (*value as i16 >> 1) as i32;
+      let offset = round_to_i16(offset * 1000.0 / 0.98) as i32;
+      let programmed = (factory - offset).clamp(-16384, 16383) as u16;
+      *value = (programmed << 1) | (*value & 0x01);
+    }
+
+    self.write_offsets(gyro, accel)?;
+    self.hardware_offsets = true;
+    Ok(())
+  }
+
+  /// Raise INT whenever a new sample is available, i.e. at the output data rate.
+  pub fn set_data_ready_interrupt(&mut self, enabled: bool) -> Result<(), Error<I2C::Error>> {
+    let int_enable = if enabled {
+      self.int_enable | INT_DATA_READY
+    } else {
+      self.int_enable & !INT_DATA_READY
+    };
+    self.set_int_enable(int_enable)
+  }
+
+  /// Raise INT when the acceleration on any axis changes by more than `threshold_mg` between two
+  /// samples.
+  ///
+  /// The threshold has a resolution of 4mg and must not exceed 1020mg. The data-ready interrupt
+  /// stays as configured, disable it to only be woken up by motion.
+  pub fn enable_wake_on_motion(&mut self, threshold_mg: u16) -> Result<(), Error<I2C::Error>> {
+    if !self.initialized {
+      return Err(Error::NotInitialized);
+    }
+    if threshold_mg > 1020 {
+      return Err(Error::InvalidArgument);
+    }
+
+    let threshold = (threshold_mg / 4) as u8;
+    self.write_register(MPU6886_ACCEL_WOM_X_THR, threshold)?;
+    self.write_register(MPU6886_ACCEL_WOM_Y_THR, threshold)?;
+    self.write_register(MPU6886_ACCEL_WOM_Z_THR, threshold)?;
+    // ACCEL_INTEL_EN, compare each sample with the previous one
+    self.write_register(MPU6886_ACCEL_INTEL_CTRL, (1 << 7) | (1 << 6))?;
+    self.set_int_enable(self.int_enable | INT_WOM)
+  }
+
+  pub fn disable_wake_on_motion(&mut self) -> Result<(), Error<I2C::Error>> {
+    self.set_int_enable(self.int_enable & !INT_WOM)?;
+    self.write_register(MPU6886_ACCEL_INTEL_CTRL, 0x00)
+  }
+
+  /// Read and clear the pending interrupts, which also releases INT.
+  pub fn get_interrupt_status(&mut self) -> Result<InterruptStatus, Error<I2C::Error>> {
+    let mut buf = [0x00u8];
+    self
+      .i2c
+      .write_read(MPU6886_ADDRESS, &[MPU6886_INT_STATUS], &mut buf)?;
+    Ok(InterruptStatus(buf[0]))
+  }
+
+  /// Run the built-in self-test and compare the response of every axis with the factory trim.
+  ///
+  /// Takes about half a second, during which the sensor has to lie still. The configuration is
+  /// restored afterwards, also when the test fails with an error.
+  pub fn self_test<D: DelayUs>(
+    &mut self,
+    delay: &mut D,
+  ) -> Result<SelfTestResult, Error<I2C::Error>> {
+    self.check_gyro_running()?;
+
+    // SMPLRT_DIV to ACCEL_CONFIG2 are restored as they were, including FIFO_MODE in CONFIG
+    let mut config = [0x00u8; 5];
+    self
+      .i2c
+      .write_read(MPU6886_ADDRESS, &[MPU6886_SMPLRT_DIV], &mut config)?;
+    let mut fifo_en = [0x00u8];
+    self
+      .i2c
+      .write_read(MPU6886_ADDRESS, &[MPU6886_FIFO_EN], &mut fifo_en)?;
+    // Keep the test samples out of the FIFO
+    self.write_register(MPU6886_FIFO_EN, 0x00)?;
+
+    let responses = self.measure_self_test_responses(delay);
+    for (reg, value) in (MPU6886_SMPLRT_DIV..).zip(config) {
+      self.write_register(reg, value)?;
+    }
+    self.write_register(MPU6886_FIFO_EN, fifo_en[0])?;
+    delay.delay_ms(20);
+    let responses = responses?;
+
+    let mut codes = [0x00u8; 6];
+    self.i2c.write_read(
+      MPU6886_ADDRESS,
+      &[MPU6886_SELF_TEST_X_GYRO],
+      &mut codes[3..6],
+    )?;
+    self.i2c.write_read(
+      MPU6886_ADDRESS,
+      &[MPU6886_SELF_TEST_X_ACCEL],
+      &mut codes[0..3],
+    )?;
+
+    // Responses in LSB of the ±2g and ±250dps ranges the test runs with
+    let mut axes = [SelfTestAxis::default(); 6];
+    for (i, axis) in axes.iter_mut().enumerate() {
+      let (lsb_per_unit, in_range) = if i < 3 {
+        // Without factory trim, 225mg to 675mg
+        (16384.0, 225.0 * 16.384..=675.0 * 16.384)
+      } else {
+        // Without factory trim, at least 60dps
+        (131.072, 60.0 * 131.072..=f32::MAX)
+      };
+      let response = responses[i];
+      let factory = match codes[i] {
+        0 => None,
+        code => Some(2620.0 * powf(1.01, code as f32 - 1.0)),
+      };
+      // The sign of the response differs between axes and units
+      let passed = match factory {
+        Some(factory) if i < 3 => (0.5..=1.5).contains(&(response.abs() / factory)),
+        Some(factory) => response.abs() / factory >= 0.5,
+        None => in_range.contains(&response.abs()),
+      };
+      *axis = SelfTestAxis {
+        response: response / lsb_per_unit,
+        factory_response: factory.map(|factory| factory / lsb_per_unit),
+        passed,
+      };
+    }
+
+    Ok(SelfTestResult {
+      accel: [axes[0], axes[1], axes[2]],
+      gyro: [axes[3], axes[4], axes[5]],
+    })
+  }
+
+  /// Read `samples` samples at the output data rate and check every axis for being stuck or
+  /// saturated.
+  ///
+  /// At least 2 samples are needed. Shaking the device hard enough saturates a healthy sensor.
+  pub fn check_health<D: DelayUs>(
+    &mut self,
+    samples: u16,
+    delay: &mut D,
+  ) -> Result<HealthReport, Error<I2C::Error>> {
+    self.check_gyro_running()?;
+    if samples < 2 {
+      return Err(Error::InvalidArgument);
+    }
+
+    let period_us = (1_000_000.0 / self.config.sample_rate_hz()) as u32;
+    let mut min = [i16::MAX; 6];
+    let mut max = [i16::MIN; 6];
+    for _ in 0..samples {
+      let raw = self.read_raw_motion()?;
+      for (i, value) in raw.into_iter().enumerate() {
+        min[i] = min[i].min(value);
+        max[i] = max[i].max(value);
+      }
+      delay.delay_us(period_us);
+    }
+
+    let mut axes = [AxisHealth::Ok; 6];
+    for (i, axis) in axes.iter_mut().enumerate() {
+      if min[i] == i16::MIN || max[i] == i16::MAX {
+        *axis = AxisHealth::Saturated;
+      } else if min[i] == max[i] {
+        *axis = AxisHealth::Stuck;
+      }
+    }
+
+    Ok(HealthReport {
+      accel: [axes[0], axes[1], axes[2]],
+      gyro: [axes[3], axes[4], axes[5]],
+    })
+  }
+
+  /// Start writing samples of the given sensors into the FIFO.
+  ///
+  /// The FIFO is cleared and stops accepting samples when full, `read_fifo` reports that as
+  /// `Error::FifoOverflow`.
+  pub fn enable_fifo(&mut self, sensors: FifoSensors) -> Result<(), Error<I2C::Error>> {
+    if !self.initialized {
+      return Err(Error::NotInitialized);
+    }
+
+    self.write_register(MPU6886_USER_CTRL, 0x00)?;
+    // FIFO_MODE: drop new samples instead of overwriting old ones
+    self.write_register(MPU6886_CONFIG, (1 << 6) | self.config.gyro_dlpf as u8)?;
+    self.write_register(MPU6886_FIFO_EN, sensors.fifo_en())?;
+    self.write_register(MPU6886_USER_CTRL, (1 << 6) | (1 << 2))?;
+
+    self.fifo = Some(sensors);
+    self.fifo_index = 0;
+    Ok(())
+  }
+
+  pub fn disable_fifo(&mut self) -> Result<(), Error<I2C::Error>> {
+    self.write_register(MPU6886_FIFO_EN, 0x00)?;
+    self.write_register(MPU6886_USER_CTRL, 0x00)?;
+    self.write_register(MPU6886_CONFIG, self.config.gyro_dlpf as u8)?;
+
+    self.fifo = None;
+    Ok(())
+  }
+
+  /// Drop all samples in the FIFO.
+  pub fn reset_fifo(&mut self) -> Result<(), Error<I2C::Error>> {
+    let user_ctrl = if self.fifo.is_some() { 1 << 6 } else { 0 };
+    self.write_register(MPU6886_USER_CTRL, user_ctrl | (1 << 2))
+  }
+
+  /// Number of bytes in the FIFO.
+  pub fn get_fifo_count(&mut self) -> Result<u16, Error<I2C::Error>> {
+    let mut buf = [0x00u8; 2];
+    self
+      .i2c
+      .write_read(MPU6886_ADDRESS, &[MPU6886_FIFO_COUNTH], &mut buf)?;
+    Ok((((buf[0] & 0x1f) as u16) << 8) | (buf[1] as u16))
+  }
+
+  /// Read as many complete samples as are available and fit into `samples`, returning how many were
+  /// written.
+  ///
+  /// After an overflow the FIFO is reset and the sample counter keeps going, so timestamps of later
+  /// samples do not account for the lost ones.
+  pub fn read_fifo(&mut self, samples: &mut [FifoSample]) -> Result<usize, Error<I2C::Error>> {
+    const PACKETS_PER_BURST: usize = 16;
+
+    if !self.initialized {
+      return Err(Error::NotInitialized);
+    }
+    let sensors = self.fifo.ok_or(Error::FifoDisabled)?;
+    match sensors {
+      FifoSensors::Accel => self.check_awake()?,
+      FifoSensors::Gyro | FifoSensors::AccelGyro => self.check_gyro_running()?,
+    }
+    let packet_size = sensors.packet_size();
+
+    let count = self.get_fifo_count()?;
+    if count >= MPU6886_FIFO_SIZE {
+      self.reset_fifo()?;
+      return Err(Error::FifoOverflow);
+    }
+
+    let available = (count as usize / packet_size).min(samples.len());
+    let mut buf = [0x00u8; 14 * PACKETS_PER_BURST];
+    let mut read = 0;
+    while read < available {
+      let packets = (available - read).min(PACKETS_PER_BURST);
+      let burst = &mut buf[..packets * packet_size];
+      self
+        .i2c
+        .write_read(MPU6886_ADDRESS, &[MPU6886_FIFO_R_W], burst)?;
+
+      for packet in burst.chunks_exact(packet_size) {
+        samples[read] = self.decode_fifo_packet(sensors, packet);
+        read += 1;
+      }
+    }
+
+    Ok(read)
+  }
+
+  fn decode_fifo_packet(&mut self, sensors: FifoSensors, packet: &[u8]) -> FifoSample {
+    let (accel, temp, gyro) = match sensors {
+      FifoSensors::Accel => (Some(&packet[0..6]), &packet[6..8], None),
+      FifoSensors::Gyro => (None, &packet[0..2], Some(&packet[2..8])),
+      FifoSensors::AccelGyro => (Some(&packet[0..6]), &packet[6..8], Some(&packet[8..14])),
+    };
+
+    let timestamp_us = self.fifo_index * self.config.fifo_period_us(sensors);
+    self.fifo_index += 1;
+
+    FifoSample {
+      timestamp_us,
+      accel: accel.map(|data| self.correct_accel(scale_xyz(data, self.a_res).into())),
+      gyro: gyro.map(|data| self.correct_gyro(scale_xyz(data, self.g_res).into())),
+      temp_c: temp_from_raw(temp),
+    }
+  }
+
+  fn read_motion_uncorrected(&mut self) -> Result<MotionSample, Error<I2C::Error>> {
+    self.check_awake()?;
+
+    let mut buf = [0x00u8; 14];
+
+    self
+      .i2c
+      .write_read(MPU6886_ADDRESS, &[MPU6886_ACCEL_XOUT_H], &mut buf)?;
+
+    Ok(MotionSample {
+      accel: scale_xyz(&buf[0..6], self.a_res).into(),
+      gyro: scale_xyz(&buf[8..14], self.g_res).into(),
+      temp_c: temp_from_raw(&buf[6..8]),
+    })
+  }
+
+  /// Change of the mean raw readings when the self-test is enabled, at the settings the factory
+  /// trim was measured with.
+  fn measure_self_test_responses<D: DelayUs>(
+    &mut self,
+    delay: &mut D,
+  ) -> Result<[f32; 6], Error<I2C::Error>> {
+    self.write_register(MPU6886_SMPLRT_DIV, 0x00)?;
+    self.write_register(MPU6886_CONFIG, GyroDlpf::Hz92 as u8)?;
+    self.write_register(MPU6886_GYRO_CONFIG, 0x00)?;
+    self.write_register(MPU6886_ACCEL_CONFIG, 0x00)?;
+    self.write_register(MPU6886_ACCEL_CONFIG2, AccelDlpf::Hz99 as u8)?;
+    delay.delay_ms(20);
+    let normal = self.mean_raw_motion(delay)?;
+
+    // Self-test of all three axes
+    self.write_register(MPU6886_GYRO_CONFIG, 0xE0)?;
+    self.write_register(MPU6886_ACCEL_CONFIG, 0xE0)?;
+    delay.delay_ms(20);
+    let enabled = self.mean_raw_motion(delay)?;
+
+    let mut responses = enabled;
+    for (response, normal) in responses.iter_mut().zip(normal) {
+      *response -= normal;
+    }
+    Ok(responses)
+  }
+
+  fn mean_raw_motion<D: DelayUs>(&mut self, delay: &mut D) -> Result<[f32; 6], Error<I2C::Error>> {
+    const SAMPLES: i32 = 200;
+
+    let mut sum = [0i32; 6];
+    for _ in 0..SAMPLES {
+      let raw = self.read_raw_motion()?;
+      for (sum, value) in sum.iter_mut().zip(raw) {
+        *sum += value as i32;
+      }
+      delay.delay_ms(1);
+    }
+    Ok(sum.map(|sum| sum as f32 / SAMPLES as f32))
+  }
+
+  /// Raw accelerometer and gyroscope outputs, X/Y/Z each.
+  fn read_raw_motion(&mut self) -> Result<[i16; 6], Error<I2C::Error>> {
+    self.check_awake()?;
+
+    let mut buf = [0x00u8; 14];
+
+    self
+      .i2c
+      .write_read(MPU6886_ADDRESS, &[MPU6886_ACCEL_XOUT_H], &mut buf)?;
+
+    Ok([0, 2, 4, 8, 10, 12].map(|at| be_i16(&buf[at..at + 2])))
+  }
+
+  /// Write the ranges, filters and sample rate of `config`.
+  fn apply_config(&mut self, config: &Mpu6886Config) -> Result<(), Error<I2C::Error>> {
+    self.set_accel_fsr(config.accel_range)?;
+    self.set_gyro_fsr(config.gyro_range)?;
+    self.write_register(MPU6886_CONFIG, config.gyro_dlpf as u8)?;
+    self.write_register(MPU6886_SMPLRT_DIV, config.sample_rate_divider)?;
+    self.write_register(MPU6886_ACCEL_CONFIG2, config.accel_config2())
+  }
+
+  /// Mean accelerometer and gyroscope readings, failing if the spread of the readings exceeds the
+  /// given limits. A limit of 0 is not checked.
+  fn measure_mean<D: DelayUs>(
+    &mut self,
+    samples: u16,
+    delay: &mut D,
+    max_accel_spread: f32,
+    max_gyro_spread: f32,
+  ) -> Result<MotionSample, Error<I2C::Error>> {
+    if samples == 0 {
+      return Err(Error::InvalidArgument);
+    }
+
+    let period_us = (1_000_000.0 / self.config.sample_rate_hz()) as u32;
+    let mut sum = [0.0f32; 7];
+    let mut min = [f32::MAX; 6];
+    let mut max = [f32::MIN; 6];
+    for _ in 0..samples {
+      let sample = self.read_motion_uncorrected()?;
+      let (a, g) = (sample.accel, sample.gyro);
+      for (i, value) in [a.x, a.y, a.z, g.x, g.y, g.z].into_iter().enumerate() {
+        sum[i] += value;
+        min[i] = min[i].min(value);
+        max[i] = max[i].max(value);
+      }
+      sum[6] += sample.temp_c;
+      delay.delay_us(period_us);
+    }
+
+    for i in 0..6 {
+      let limit = if i < 3 {
+        max_accel_spread
+      } else {
+        max_gyro_spread
+      };
+      if limit > 0.0 && max[i] - min[i] > limit {
+        return Err(Error::NotStationary);
+      }
+    }
+
+    let mean = sum.map(|value| value / samples as f32);
+    Ok(MotionSample {
+      accel: Acceleration::new(mean[0], mean[1], mean[2]),
+      gyro: AngularRate::new(mean[3], mean[4], mean[5]),
+      temp_c: mean[6],
+    })
+  }
+
+  fn correct_accel(&self, accel: Acceleration) -> Acceleration {
+    if self.hardware_offsets {
+      accel.vector().scale(self.calibration.accel_scale).into()
+    } else {
+      self.calibration.apply_accel(accel)
+    }
+  }
+
+  fn correct_gyro(&self, gyro: AngularRate) -> AngularRate {
+    if self.hardware_offsets {
+      gyro
+    } else {
+      self.calibration.apply_gyro(gyro)
+    }
+  }
+
+  fn write_offsets(&mut self, gyro: [i16; 3], accel: [u16; 3]) -> Result<(), Error<I2C::Error>> {
+    let mut buf = [MPU6886_XG_OFFS_USRH, 0, 0, 0, 0, 0, 0];
+    for (i, value) in gyro.iter().enumerate() {
+      buf[1 + i * 2..3 + i * 2].copy_from_slice(&value.to_be_bytes());
+    }
+    self.i2c.write(MPU6886_ADDRESS, &buf)?;
+
+    for (i, value) in accel.iter().enumerate() {
+      let [high, low] = value.to_be_bytes();
+      let reg = MPU6886_XA_OFFSET_H + i as u8 * 3;
+      self.write_register(reg, high)?;
+      self.write_register(reg + 1, low)?;
+    }
+    Ok(())
+  }
+
+  fn set_int_enable(&mut self, int_enable: u8) -> Result<(), Error<I2C::Error>> {
+    self.write_register(MPU6886_INT_ENABLE, int_enable)?;
+    self.int_enable = int_enable;
+    Ok(())
+  }
+
+  fn check_awake(&self) -> Result<(), Error<I2C::Error>> {
+    if !self.initialized {
+      return Err(Error::NotInitialized);
+    }
+    if self.power_mode == PowerMode::Sleep {
+      return Err(Error::Sleeping);
+    }
+    Ok(())
+  }
+
+  fn check_gyro_running(&self) -> Result<(), Error<I2C::Error>> {
+    self.check_awake()?;
+    match self.power_mode {
+      PowerMode::GyroStandby | PowerMode::AccelLowPower => Err(Error::GyroDisabled),
+      _ => Ok(()),
+    }
+  }
+
+  fn write_register(&mut self, reg: u8, value: u8) -> Result<(), Error<I2C::Error>> {
+    self.i2c.write(MPU6886_ADDRESS, &[reg, value])?;
+    Ok(())
+  }
+}
+
+fn be_i16(bytes: &[u8]) -> i16 {
+  i16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+/// Scale three big-endian words by `res`.
+fn scale_xyz(data: &[u8], res: f32) -> Vector3 {
+  Vector3::new(
+    (be_i16(&data[0..2]) as f32) * res,
+    (be_i16(&data[2..4]) as f32) * res,
+    (be_i16(&data[4..6]) as f32) * res,
+  )
+}
+
+fn round_to_i16(value: f32) -> i16 {
+  let value = if value < 0.0 {
+    value - 0.5
+  } else {
+    value + 0.5
+  };
+  value.clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// Die temperature in degrees Celsius from TEMP_OUT.
+fn temp_from_raw(data: &[u8]) -> f32 {
+  (be_i16(data) as f32) / 326.8 + 25.0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sample_rate() {
+    let config = Mpu6886Config::default();
+    assert_eq!(config.sample_rate_hz(), 1000.0 / 6.0);
+    assert_eq!(config.sample_rate_divider(0).sample_rate_hz(), 1000.0);
+
+    // The divider is ignored at the 8kHz internal rate
+    for dlpf in [GyroDlpf::Hz250, GyroDlpf::Hz3281] {
+      let config = config.gyro_dlpf(dlpf);
+      assert_eq!(config.sample_rate_hz(), 8000.0);
+      assert_eq!(config.sample_rate_divider(0).sample_rate_hz(), 8000.0);
+    }
   }
 }
@@ -0,0 +1,317 @@
+use core::time::Duration;
+
+use crate::time::Instant;
+use crate::vector::Acceleration;
+
+/// Tuning of `Pedometer`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PedometerConfig {
+  /// Smallest swing of the acceleration magnitude around its mean that counts as a step.
+  pub min_threshold_g: f32,
+  /// Steps closer together than this are bounces of the same step.
+  pub min_step_interval: Duration,
+  /// A longer pause ends the walk.
+  pub max_step_interval: Duration,
+  /// Steps in a row before any are counted, so single bumps do not add steps.
+  pub steps_to_start: u8,
+}
+
+impl Default for PedometerConfig {
+  fn default() -> Self {
+    Self {
+      min_threshold_g: 0.1,
+      min_step_interval: Duration::from_millis(250),
+      max_step_interval: Duration::from_millis(2000),
+      steps_to_start: 4,
+    }
+  }
+}
+
+/// Counts steps from the magnitude of the acceleration, independent of how the device is worn.
+///
+/// The magnitude is smoothed, its slowly moving mean removed and every rise above an adaptive
+/// threshold is a step candidate. The threshold follows the recent peak-to-peak swing of the
+/// signal, so it recovers within a few seconds after a jolt. Candidates are only counted once
+/// `steps_to_start` of them followed each other at a walking pace.
+pub struct Pedometer {
+  config: PedometerConfig,
+  count: u32,
+  last_time: Option<Instant>,
+  smooth: f32,
+  mean: f32,
+  above: bool,
+  positive: bool,
+  /// Extremes of the signal since it last rose through 0.
+  cycle_max: f32,
+  cycle_min: f32,
+  /// Peak-to-peak value of the last complete cycle.
+  swing: f32,
+  /// `swing` smoothed over `AMPLITUDE_TIME_CONSTANT`.
+  amplitude: f32,
+  last_step: Option<Instant>,
+  pending: u8,
+  walking: bool,
+  intervals: [Duration; 4],
+  interval_count: usize,
+}
+
+impl Pedometer {
+  const SMOOTH_TIME_CONSTANT: Duration = Duration::from_millis(40);
+  const MEAN_TIME_CONSTANT: Duration = Duration::from_millis(1000);
+  const AMPLITUDE_TIME_CONSTANT: Duration = Duration::from_millis(1000);
+
+  pub fn new(config: PedometerConfig) -> Self {
+    Self {
+      config,
+      count: 0,
+      last_time: None,
+      smooth: 0.0,
+      mean: 0.0,
+      above: false,
+      positive: false,
+      cycle_max: 0.0,
+      cycle_min: 0.0,
+      swing: 0.0,
+      amplitude: 0.0,
+      last_step: None,
+      pending: 0,
+      walking: false,
+      intervals: [Duration::ZERO; 4],
+      interval_count: 0,
+    }
+  }
+
+  pub fn config(&self) -> &PedometerConfig {
+    &self.config
+  }
+
+  pub fn step_count(&self) -> u32 {
+    self.count
+  }
+
+  /// Steps per minute over the last few steps, 0 when not walking.
+  pub fn cadence(&self) -> f32 {
+    if !self.walking || self.interval_count == 0 {
+      return 0.0;
+    }
+    let intervals = &self.intervals[..self.interval_count];
+    let mean = intervals.iter().sum::<Duration>() / intervals.len() as u32;
+    60.0 / mean.as_secs_f32()
+  }
+
+  /// Set the step count to 0 and forget the current walk.
+  pub fn reset(&mut self) {
+    *self = Self::new(self.config);
+  }
+
+  /// Process one reading taken at `now`, e.g. `SystemClock.now()`, returning the number of steps it
+  /// added to the count.
+  pub fn update(&mut self, now: Instant, accel: Acceleration) -> u32 {
+    let magnitude = accel.norm();
+    let Some(last_time) = self.last_time.replace(now) else {
+      self.smooth = magnitude;
+      self.mean = magnitude;
+      return 0;
+    };
+    let dt = now - last_time;
+    let smooth_alpha = alpha(dt, Self::SMOOTH_TIME_CONSTANT);
+    let mean_alpha = alpha(dt, Self::MEAN_TIME_CONSTANT);
+    let amplitude_alpha = alpha(dt, Self::AMPLITUDE_TIME_CONSTANT);
+    self.smooth += (magnitude - self.smooth) * smooth_alpha;
+    self.mean += (magnitude - self.mean) * mean_alpha;
+    let signal = self.smooth - self.mean;
+
+    // A cycle ends whenever the signal rises through the mean
+    if signal >= 0.0 && !self.positive {
+      self.swing = self.cycle_max - self.cycle_min;
+      self.cycle_max = signal;
+      self.cycle_min = signal;
+    } else {
+      self.cycle_max = self.cycle_max.max(signal);
+      self.cycle_min = self.cycle_min.min(signal);
+    }
+    self.positive = signal >= 0.0;
+    self.amplitude += (self.swing - self.amplitude) * amplitude_alpha;
+
+    if let Some(last_step) = self.last_step {
+      if now - last_step > self.config.max_step_interval {
+        self.end_walk();
+      }
+    }
+
+    if self.above {
+      if signal < 0.0 {
+        self.above = false;
+      }
+      return 0;
+    }
+
+    // Half way up from the mean to a typical peak
+    let threshold = self.config.min_threshold_g.max(self.amplitude / 4.0);
+    if signal <= threshold {
+      return 0;
+    }
+    self.above = true;
+    self.step(now)
+  }
+
+  fn step(&mut self, now: Instant) -> u32 {
+    if let Some(last_step) = self.last_step {
+      let interval = now - last_step;
+      if interval < self.config.min_step_interval {
+        return 0;
+      }
+      if self.interval_count == self.intervals.len() {
+        self.intervals.rotate_left(1);
+        self.interval_count -= 1;
+      }
+      self.intervals[self.interval_count] = interval;
+      self.interval_count += 1;
+    }
+    self.last_step = Some(now);
+
+    if self.walking {
+      self.count += 1;
+      return 1;
+    }
+    self.pending += 1;
+    if self.pending < self.config.steps_to_start {
+      return 0;
+    }
+    self.walking = true;
+    let steps = self.pending as u32;
+    self.count += steps;
+    self.pending = 0;
+    steps
+  }
+
+  fn end_walk(&mut self) {
+    self.last_step = None;
+    self.pending = 0;
+    self.walking = false;
+    self.interval_count = 0;
+    self.swing = 0.0;
+    self.amplitude = 0.0;
+  }
+}
+
+impl Default for Pedometer {
+  fn default() -> Self {
+    Self::new(PedometerConfig::default())
+  }
+}
+
+/// Weight of a new reading `dt` after the last one in a low pass filter with `time_constant`.
+fn alpha(dt: Duration, time_constant: Duration) -> f32 {
+  let dt = dt.as_secs_f32();
+  dt / (time_constant.as_secs_f32() + dt)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::vec::Vec;
+  use core::f32::consts::PI;
+
+  const PERIOD_MS: u32 = 20;
+
+  /// Magnitude of a walk at `steps_per_minute` with a swing of `amplitude_g` around 1g, with a
+  /// harmonic for the heel strike and some deterministic noise.
+  fn walk(start_ms: u32, seconds: u32, steps_per_minute: f32, amplitude_g: f32) -> Vec<(u32, f32)> {
+    let mut noise = 12345u32;
+    (0..seconds * 1000 / PERIOD_MS)
+      .map(|i| {
+        let t = (i * PERIOD_MS) as f32 / 1000.0;
+        let phase = 2.0 * PI * steps_per_minute / 60.0 * t;
+        noise = noise.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        let jitter = ((noise >> 16) % 1000) as f32 / 1000.0 - 0.5;
+        let magnitude = 1.0
+          + amplitude_g * libm::sinf(phase)
+          + amplitude_g * 0.3 * libm::sinf(2.0 * phase)
+          + 0.02 * jitter;
+        (start_ms + i * PERIOD_MS, magnitude)
+      })
+      .collect()
+  }
+
+  fn rest(start_ms: u32, seconds: u32) -> Vec<(u32, f32)> {
+    (0..seconds * 1000 / PERIOD_MS)
+      .map(|i| (start_ms + i * PERIOD_MS, 1.0))
+      .collect()
+  }
+
+  fn feed(pedometer: &mut Pedometer, trace: &[(u32, f32)]) {
+    for &(timestamp_ms, magnitude) in trace {
+      let now = Instant::from_millis(timestamp_ms as u64);
+      pedometer.update(now, Acceleration::new(0.0, 0.0, magnitude));
+    }
+  }
+
+  #[test]
+  fn counts_steps_and_cadence() {
+    for (steps_per_minute, amplitude_g) in [(100.0, 0.3), (120.0, 0.5), (150.0, 0.8)] {
+      let mut pedometer = Pedometer::default();
+      feed(&mut pedometer, &walk(0, 20, steps_per_minute, amplitude_g));
+      let expected = steps_per_minute / 3.0;
+      let count = pedometer.step_count() as f32;
+      assert!(
+        (count - expected).abs() <= 2.0,
+        "{count} steps instead of {expected}"
+      );
+      let cadence = pedometer.cadence();
+      assert!(
+        (cadence - steps_per_minute).abs() < 5.0,
+        "cadence {cadence}"
+      );
+    }
+  }
+
+  #[test]
+  fn ignores_standing_still() {
+    let mut pedometer = Pedometer::default();
+    feed(&mut pedometer, &rest(0, 10));
+    assert_eq!(pedometer.step_count(), 0);
+    assert_eq!(pedometer.cadence(), 0.0);
+  }
+
+  #[test]
+  fn single_bumps_are_not_steps() {
+    let mut pedometer = Pedometer::default();
+    let mut trace = rest(0, 2);
+    trace.extend(walk(2000, 1, 120.0, 0.5).into_iter().take(25));
+    trace.extend(rest(2500, 5));
+    feed(&mut pedometer, &trace);
+    assert_eq!(pedometer.step_count(), 0);
+  }
+
+  #[test]
+  fn counts_walk_after_jolt() {
+    let mut pedometer = Pedometer::default();
+    let mut trace = rest(0, 2);
+    // A jump: 3g for 100ms, then falling back
+    trace.extend((0..5).map(|i| (2000 + i * PERIOD_MS, 3.0)));
+    trace.extend((0..5).map(|i| (2100 + i * PERIOD_MS, 0.2)));
+    trace.extend(rest(2200, 1));
+    trace.extend(walk(3200, 20, 110.0, 0.25));
+    feed(&mut pedometer, &trace);
+
+    let expected = 110.0 / 3.0;
+    let count = pedometer.step_count() as f32;
+    assert!(
+      (count - expected).abs() <= 2.0,
+      "{count} steps instead of {expected}"
+    );
+    assert!((pedometer.cadence() - 110.0).abs() < 5.0);
+  }
+
+  #[test]
+  fn threshold_recovers_after_walk_ends() {
+    let mut pedometer = Pedometer::default();
+    let mut trace = walk(0, 10, 120.0, 1.5);
+    trace.extend(rest(10_000, 3));
+    trace.extend(walk(13_000, 10, 120.0, 0.25));
+    feed(&mut pedometer, &trace);
+    let count = pedometer.step_count() as f32;
+    assert!((count - 40.0).abs() <= 3.0, "{count} steps instead of 40");
+  }
+}
@@ -0,0 +1,645 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt;
+
+use libm::roundf;
+
+use crate::axp192::{self, Axp192};
+use crate::calibration::fletcher16;
+use crate::mpu6886::MotionSample;
+use crate::vector::{Acceleration, AngularRate};
+
+/// Start of every recording: magic and format version.
+pub const HEADER: [u8; 5] = [b'M', b'5', b'R', b'C', 1];
+
+/// Power readings recorded alongside the motion.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PowerSample {
+  /// Battery voltage in V.
+  pub bat_voltage: f32,
+  /// Battery current in mA, positive while charging.
+  pub bat_current: f32,
+  /// USB voltage in V.
+  pub vbus_voltage: f32,
+  /// USB current in mA.
+  pub vbus_current: f32,
+}
+
+impl PowerSample {
+  pub fn read<I2C>(axp: &mut Axp192<I2C>) -> Result<Self, axp192::Error<I2C::Error>>
+  where
+    I2C: embedded_hal::i2c::I2c,
+  {
+    Ok(Self {
+      bat_voltage: axp.get_bat_voltage()?,
+      bat_current: axp.get_bat_current()?,
+      vbus_voltage: axp.get_vbus_voltage()?,
+      vbus_current: axp.get_vbus_current()?,
+    })
+  }
+}
+
+/// One entry of a recording.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Record {
+  Motion {
+    timestamp_us: u64,
+    sample: MotionSample,
+  },
+  Power {
+    timestamp_us: u64,
+    sample: PowerSample,
+  },
+}
+
+impl Record {
+  pub fn timestamp_us(&self) -> u64 {
+    match self {
+      Record::Motion { timestamp_us, .. } | Record::Power { timestamp_us, .. } => *timestamp_us,
+    }
+  }
+}
+
+/// Reasons a recording could not be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+  /// The data does not start with `HEADER`, or was written by an incompatible version
+  UnknownFormat,
+  /// The data ends in the middle of a block
+  Truncated,
+  /// A block is corrupted
+  ChecksumMismatch,
+  /// A block passed the checksum but contains an unknown record
+  InvalidRecord,
+}
+
+/// Result of `decode`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decoded {
+  pub records: Vec<Record>,
+  /// Why decoding stopped before the end of the data, e.g. `Truncated` for a block cut short by a
+  /// power loss while writing. `records` holds everything before the bad block.
+  pub error: Option<DecodeError>,
+  /// Bytes up to the end of the last good block, including the header.
+  pub valid_len: usize,
+}
+
+/// Records timestamped motion and power samples into a bounded ring buffer.
+///
+/// Samples are quantized (accelerometer to 0.1mg, gyroscope to 0.01dps, temperatures to 0.01°C,
+/// voltages to 1mV and currents to 0.1mA) and stored as variable-length differences to the
+/// previous sample of the same kind, so slow motion takes a few bytes per sample.
+///
+/// The data is split into blocks of at most `block_size` bytes that can be decoded on their own.
+/// Once `capacity` is reached the oldest block is dropped. A recording is `HEADER` followed by
+/// blocks in order; `to_bytes` returns the whole buffer, while `pop_block` moves completed blocks
+/// out, e.g. to append them to a flash partition. `decode` turns a recording back into records.
+pub struct Recorder {
+  block_size: usize,
+  max_blocks: usize,
+  blocks: VecDeque<Vec<u8>>,
+  current: Vec<u8>,
+  encoder: DeltaState,
+  dropped_blocks: u32,
+}
+
+impl Recorder {
+  /// Block size used by `new`, a multiple of the flash page size.
+  pub const DEFAULT_BLOCK_SIZE: usize = 512;
+
+  /// Every block starts with its payload length and checksum.
+  const BLOCK_HEADER_SIZE: usize = 4;
+  /// Longest encoded record: tag, timestamp and seven values.
+  const MAX_RECORD_SIZE: usize = 1 + 10 + 7 * 5;
+
+  /// A recorder keeping about the last `capacity` bytes.
+  pub fn new(capacity: usize) -> Self {
+    Self::with_block_size(capacity, Self::DEFAULT_BLOCK_SIZE)
+  }
+
+  /// Smaller blocks waste less of the capacity when the oldest one is dropped, larger blocks
+  /// compress better. `block_size` is raised to fit at least one record.
+  pub fn with_block_size(capacity: usize, block_size: usize) -> Self {
+    let block_size = block_size
+      .max(Self::BLOCK_HEADER_SIZE + Self::MAX_RECORD_SIZE)
+      .min(u16::MAX as usize);
+    Self {
+      block_size,
+      max_blocks: (capacity / block_size).max(1),
+      blocks: VecDeque::new(),
+      current: Vec::new(),
+      encoder: DeltaState::default(),
+      dropped_blocks: 0,
+    }
+  }
+
+  pub fn record_motion(&mut self, timestamp_us: u64, sample: &MotionSample) {
+    self.record(&Record::Motion {
+      timestamp_us,
+      sample: *sample,
+    });
+  }
+
+  pub fn record_power(&mut self, timestamp_us: u64, sample: &PowerSample) {
+    self.record(&Record::Power {
+      timestamp_us,
+      sample: *sample,
+    });
+  }
+
+  pub fn record(&mut self, record: &Record) {
+    let mut encoded = Vec::with_capacity(Self::MAX_RECORD_SIZE);
+    let mut encoder = self.encoder;
+    encoder.encode(record, &mut encoded);
+    if Self::BLOCK_HEADER_SIZE + self.current.len() + encoded.len() > self.block_size {
+      self.finish_block();
+      encoded.clear();
+      encoder = DeltaState::default();
+      encoder.encode(record, &mut encoded);
+    }
+    self.current.extend_from_slice(&encoded);
+    self.encoder = encoder;
+  }
+
+  /// Bytes recorded, without the header.
+  pub fn len(&self) -> usize {
+    let current = match self.current.len() {
+      0 => 0,
+      len => Self::BLOCK_HEADER_SIZE + len,
+    };
+    self.blocks.iter().map(Vec::len).sum::<usize>() + current
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.blocks.is_empty() && self.current.is_empty()
+  }
+
+  /// Blocks overwritten because the buffer was full.
+  pub fn dropped_blocks(&self) -> u32 {
+    self.dropped_blocks
+  }
+
+  pub fn clear(&mut self) {
+    self.blocks.clear();
+    self.current.clear();
+    self.encoder = DeltaState::default();
+    self.dropped_blocks = 0;
+  }
+
+  /// Remove the oldest completed block. The block currently being filled is only returned by
+  /// `flush`.
+  pub fn pop_block(&mut self) -> Option<Vec<u8>> {
+    self.blocks.pop_front()
+  }
+
+  /// Complete the block currently being filled, so `pop_block` returns it as well.
+  pub fn flush(&mut self) {
+    if !self.current.is_empty() {
+      self.finish_block();
+    }
+  }
+
+  /// The whole recording, including the block currently being filled.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER.len() + self.len());
+    bytes.extend_from_slice(&HEADER);
+    for block in &self.blocks {
+      bytes.extend_from_slice(block);
+    }
+    if !self.current.is_empty() {
+      write_block(&self.current, &mut bytes);
+    }
+    bytes
+  }
+
+  fn finish_block(&mut self) {
+    let mut block = Vec::with_capacity(Self::BLOCK_HEADER_SIZE + self.current.len());
+    write_block(&self.current, &mut block);
+    if self.blocks.len() == self.max_blocks {
+      self.blocks.pop_front();
+      self.dropped_blocks += 1;
+    }
+    self.blocks.push_back(block);
+    self.current.clear();
+    self.encoder = DeltaState::default();
+  }
+}
+
+/// Decode a recording written by `Recorder::to_bytes`, or `HEADER` followed by blocks returned by
+/// `Recorder::pop_block`.
+///
+/// Erased flash after the last block ends the recording, so a whole partition can be decoded. A bad
+/// block ends it as well, see `Decoded::error`; only data without `HEADER` fails as a whole.
+pub fn decode(bytes: &[u8]) -> Result<Decoded, DecodeError> {
+  let mut rest = bytes
+    .strip_prefix(&HEADER[..])
+    .ok_or(DecodeError::UnknownFormat)?;
+
+  let mut decoded = Decoded {
+    records: Vec::new(),
+    error: None,
+    valid_len: HEADER.len(),
+  };
+  while !rest.is_empty() && !rest.starts_with(&[0xFF, 0xFF]) {
+    match decode_block(rest, &mut decoded.records) {
+      Ok(len) => {
+        rest = &rest[len..];
+        decoded.valid_len += len;
+      }
+      Err(error) => {
+        decoded.error = Some(error);
+        break;
+      }
+    }
+  }
+  Ok(decoded)
+}
+
+/// Append the records of the block at the start of `bytes`, returning the size of the block.
+/// Nothing is appended if the block is bad.
+fn decode_block(bytes: &[u8], records: &mut Vec<Record>) -> Result<usize, DecodeError> {
+  if bytes.len() < Recorder::BLOCK_HEADER_SIZE {
+    return Err(DecodeError::Truncated);
+  }
+  let len = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+  let checksum = u16::from_le_bytes([bytes[2], bytes[3]]);
+  let mut payload = bytes[Recorder::BLOCK_HEADER_SIZE..]
+    .get(..len)
+    .ok_or(DecodeError::Truncated)?;
+  if fletcher16(payload) != checksum {
+    return Err(DecodeError::ChecksumMismatch);
+  }
+
+  let start = records.len();
+  let mut decoder = DeltaState::default();
+  while !payload.is_empty() {
+    match decoder.decode(&mut payload) {
+      Ok(record) => records.push(record),
+      Err(error) => {
+        records.truncate(start);
+        return Err(error);
+      }
+    }
+  }
+  Ok(Recorder::BLOCK_HEADER_SIZE + len)
+}
+
+/// Write `records` as CSV with a header line. Columns of the other kind of record stay empty.
+pub fn write_csv<W: fmt::Write>(records: &[Record], out: &mut W) -> fmt::Result {
+  writeln!(
+    out,
+    "timestamp_us,accel_x_g,accel_y_g,accel_z_g,gyro_x_dps,gyro_y_dps,gyro_z_dps,temp_c,\
+     bat_voltage_v,bat_current_ma,vbus_voltage_v,vbus_current_ma"
+  )?;
+  for record in records {
+    match record {
+      Record::Motion {
+        timestamp_us,
+        sample,
+      } => {
+        let (a, g) = (sample.accel, sample.gyro);
+        writeln!(
+          out,
+          "{},{:.4},{:.4},{:.4},{:.2},{:.2},{:.2},{:.2},,,,",
+          timestamp_us, a.x, a.y, a.z, g.x, g.y, g.z, sample.temp_c
+        )?;
+      }
+      Record::Power {
+        timestamp_us,
+        sample,
+      } => {
+        writeln!(
+          out,
+          "{},,,,,,,,{:.3},{:.1},{:.3},{:.1}",
+          timestamp_us,
+          sample.bat_voltage,
+          sample.bat_current,
+          sample.vbus_voltage,
+          sample.vbus_current
+        )?;
+      }
+    }
+  }
+  Ok(())
+}
+
+const TAG_MOTION: u8 = 0x01;
+const TAG_POWER: u8 = 0x02;
+
+// Quantization steps per unit
+const ACCEL_STEPS_PER_G: f32 = 10_000.0;
+const GYRO_STEPS_PER_DPS: f32 = 100.0;
+const TEMP_STEPS_PER_C: f32 = 100.0;
+const VOLTAGE_STEPS_PER_V: f32 = 1000.0;
+const CURRENT_STEPS_PER_MA: f32 = 10.0;
+
+/// Values of the previous record of each kind, which the next one is stored relative to.
+#[derive(Clone, Copy, Default)]
+struct DeltaState {
+  timestamp_us: u64,
+  motion: [i32; 7],
+  power: [i32; 4],
+}
+
+impl DeltaState {
+  fn encode(&mut self, record: &Record, out: &mut Vec<u8>) {
+    let timestamp_us = record.timestamp_us();
+    let (tag, values, previous) = match record {
+      Record::Motion { sample, .. } => {
+        let (a, g) = (sample.accel, sample.gyro);
+        let values = [
+          quantize(a.x, ACCEL_STEPS_PER_G),
+          quantize(a.y, ACCEL_STEPS_PER_G),
+          quantize(a.z, ACCEL_STEPS_PER_G),
+          quantize(g.x, GYRO_STEPS_PER_DPS),
+          quantize(g.y, GYRO_STEPS_PER_DPS),
+          quantize(g.z, GYRO_STEPS_PER_DPS),
+          quantize(sample.temp_c, TEMP_STEPS_PER_C),
+        ];
+        (TAG_MOTION, values, &mut self.motion[..])
+      }
+      Record::Power { sample, .. } => {
+        let values = [
+          quantize(sample.bat_voltage, VOLTAGE_STEPS_PER_V),
+          quantize(sample.bat_current, CURRENT_STEPS_PER_MA),
+          quantize(sample.vbus_voltage, VOLTAGE_STEPS_PER_V),
+          quantize(sample.vbus_current, CURRENT_STEPS_PER_MA),
+          0,
+          0,
+          0,
+        ];
+        (TAG_POWER, values, &mut self.power[..])
+      }
+    };
+
+    out.push(tag);
+    write_varint(
+      zigzag(timestamp_us.wrapping_sub(self.timestamp_us) as i64),
+      out,
+    );
+    for (value, previous) in values.iter().zip(previous.iter_mut()) {
+      write_varint(zigzag(value.wrapping_sub(*previous) as i64), out);
+      *previous = *value;
+    }
+    self.timestamp_us = timestamp_us;
+  }
+
+  fn decode(&mut self, bytes: &mut &[u8]) -> Result<Record, DecodeError> {
+    let (&tag, rest) = bytes.split_first().ok_or(DecodeError::InvalidRecord)?;
+    *bytes = rest;
+    let delta = unzigzag(read_varint(bytes)?);
+    let timestamp_us = self.timestamp_us.wrapping_add(delta as u64);
+    self.timestamp_us = timestamp_us;
+
+    let previous = match tag {
+      TAG_MOTION => &mut self.motion[..],
+      TAG_POWER => &mut self.power[..],
+      _ => return Err(DecodeError::InvalidRecord),
+    };
+    let mut values = [0.0f32; 7];
+    for (value, previous) in values.iter_mut().zip(previous.iter_mut()) {
+      let delta = unzigzag(read_varint(bytes)?) as i32;
+      *previous = previous.wrapping_add(delta);
+      *value = *previous as f32;
+    }
+
+    Ok(match tag {
+      TAG_MOTION => {
+        let [ax, ay, az, gx, gy, gz, temp] = values;
+        Record::Motion {
+          timestamp_us,
+          sample: MotionSample {
+            accel: Acceleration::new(ax, ay, az) * (1.0 / ACCEL_STEPS_PER_G),
+            gyro: AngularRate::new(gx, gy, gz) * (1.0 / GYRO_STEPS_PER_DPS),
+            temp_c: temp / TEMP_STEPS_PER_C,
+          },
+        }
+      }
+      _ => Record::Power {
+        timestamp_us,
+        sample: PowerSample {
+          bat_voltage: values[0] / VOLTAGE_STEPS_PER_V,
+          bat_current: values[1] / CURRENT_STEPS_PER_MA,
+          vbus_voltage: values[2] / VOLTAGE_STEPS_PER_V,
+          vbus_current: values[3] / CURRENT_STEPS_PER_MA,
+        },
+      },
+    })
+  }
+}
+
+fn write_block(payload: &[u8], out: &mut Vec<u8>) {
+  out.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+  out.extend_from_slice(&fletcher16(payload).to_le_bytes());
+  out.extend_from_slice(payload);
+}
+
+fn quantize(value: f32, steps: f32) -> i32 {
+  roundf(value * steps) as i32
+}
+
+fn zigzag(value: i64) -> u64 {
+  ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+  (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// LEB128
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+  while value >= 0x80 {
+    out.push(value as u8 | 0x80);
+    value >>= 7;
+  }
+  out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, DecodeError> {
+  let mut value = 0u64;
+  for shift in (0..64).step_by(7) {
+    let (&byte, rest) = bytes.split_first().ok_or(DecodeError::InvalidRecord)?;
+    *bytes = rest;
+    value |= ((byte & 0x7F) as u64) << shift;
+    if byte & 0x80 == 0 {
+      return Ok(value);
+    }
+  }
+  Err(DecodeError::InvalidRecord)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn motion(i: u32) -> Record {
+    let t = i as f32;
+    Record::Motion {
+      timestamp_us: 10_000 * i as u64,
+      sample: MotionSample {
+        accel: Acceleration::new(0.01 * t, -0.5 + 0.001 * t, 1.0),
+        gyro: AngularRate::new(1.5 * t, -0.25, 100.0 - t),
+        temp_c: 30.0 + 0.01 * t,
+      },
+    }
+  }
+
+  fn power(i: u32) -> Record {
+    Record::Power {
+      timestamp_us: 10_000 * i as u64 + 5_000,
+      sample: PowerSample {
+        bat_voltage: 4.1 - 0.001 * i as f32,
+        bat_current: -45.3,
+        vbus_voltage: 0.0,
+        vbus_current: 0.0,
+      },
+    }
+  }
+
+  fn recorder(records: u32) -> Recorder {
+    let mut recorder = Recorder::with_block_size(1 << 20, 128);
+    for i in 0..records {
+      recorder.record(&motion(i));
+      if i % 10 == 0 {
+        recorder.record(&power(i));
+      }
+    }
+    recorder
+  }
+
+  fn assert_same(decoded: &[Record], expected: &[Record]) {
+    assert_eq!(decoded.len(), expected.len());
+    for (decoded, expected) in decoded.iter().zip(expected) {
+      assert_eq!(decoded.timestamp_us(), expected.timestamp_us());
+      match (decoded, expected) {
+        (Record::Motion { sample: d, .. }, Record::Motion { sample: e, .. }) => {
+          assert!((d.accel - e.accel).norm() < 1e-4);
+          assert!((d.gyro - e.gyro).norm() < 1e-2);
+          assert!((d.temp_c - e.temp_c).abs() < 1e-2);
+        }
+        (Record::Power { sample: d, .. }, Record::Power { sample: e, .. }) => {
+          assert!((d.bat_voltage - e.bat_voltage).abs() < 1e-3);
+          assert!((d.bat_current - e.bat_current).abs() < 0.1);
+        }
+        _ => panic!("{decoded:?} instead of {expected:?}"),
+      }
+    }
+  }
+
+  fn expected(records: u32) -> Vec<Record> {
+    let mut expected = Vec::new();
+    for i in 0..records {
+      expected.push(motion(i));
+      if i % 10 == 0 {
+        expected.push(power(i));
+      }
+    }
+    expected
+  }
+
+  #[test]
+  fn round_trip() {
+    let recorder = recorder(100);
+    let bytes = recorder.to_bytes();
+    // Half of what the samples take as f32 with a u64 timestamp
+    assert!(bytes.len() < 110 * 36 / 2, "{} bytes", bytes.len());
+    let decoded = decode(&bytes).unwrap();
+    assert_eq!(decoded.error, None);
+    assert_eq!(decoded.valid_len, bytes.len());
+    assert_same(&decoded.records, &expected(100));
+  }
+
+  #[test]
+  fn round_trip_popped_blocks() {
+    let mut recorder = recorder(100);
+    recorder.flush();
+    let mut bytes = HEADER.to_vec();
+    while let Some(block) = recorder.pop_block() {
+      bytes.extend_from_slice(&block);
+    }
+    assert!(recorder.is_empty());
+    // Erased flash after the recording
+    bytes.resize(bytes.len() + 64, 0xFF);
+    let decoded = decode(&bytes).unwrap();
+    assert_eq!(decoded.error, None);
+    assert_same(&decoded.records, &expected(100));
+  }
+
+  #[test]
+  fn truncated_tail_keeps_earlier_blocks() {
+    let mut recorder = recorder(100);
+    recorder.flush();
+    let last_block = recorder.blocks.back().unwrap().len();
+    let bytes = recorder.to_bytes();
+    let complete = bytes.len() - last_block;
+
+    for cut in [1, 3, last_block / 2, last_block - 1] {
+      let decoded = decode(&bytes[..complete + cut]).unwrap();
+      assert_eq!(decoded.error, Some(DecodeError::Truncated));
+      assert_eq!(decoded.valid_len, complete);
+
+      let all = decode(&bytes[..complete]).unwrap();
+      assert_eq!(all.error, None);
+      assert_eq!(decoded.records, all.records);
+      assert!(!decoded.records.is_empty());
+      assert_same(&decoded.records, &expected(100)[..decoded.records.len()]);
+    }
+  }
+
+  #[test]
+  fn corrupt_block_keeps_earlier_blocks() {
+    let mut recorder = recorder(100);
+    recorder.flush();
+    let first_block = recorder.blocks.front().unwrap().len();
+    let mut bytes = recorder.to_bytes();
+    bytes[HEADER.len() + first_block + 10] ^= 0x40;
+
+    let decoded = decode(&bytes).unwrap();
+    assert_eq!(decoded.error, Some(DecodeError::ChecksumMismatch));
+    assert_eq!(decoded.valid_len, HEADER.len() + first_block);
+    let first = decode(&bytes[..HEADER.len() + first_block]).unwrap();
+    assert_eq!(decoded.records, first.records);
+  }
+
+  #[test]
+  fn unknown_format() {
+    assert_eq!(decode(b"M5RC\x02"), Err(DecodeError::UnknownFormat));
+    assert_eq!(decode(&[]), Err(DecodeError::UnknownFormat));
+  }
+
+  #[test]
+  fn drops_oldest_blocks_and_clear_resets() {
+    let mut recorder = Recorder::with_block_size(256, 128);
+    for i in 0..200 {
+      recorder.record(&motion(i));
+    }
+    assert!(recorder.dropped_blocks() > 0);
+    assert!(recorder.len() <= 256 + 128);
+    let decoded = decode(&recorder.to_bytes()).unwrap();
+    assert_eq!(decoded.error, None);
+    assert_same(
+      &decoded.records,
+      &(200 - decoded.records.len() as u32..200)
+        .map(motion)
+        .collect::<Vec<_>>(),
+    );
+
+    recorder.clear();
+    assert!(recorder.is_empty());
+    assert_eq!(recorder.dropped_blocks(), 0);
+    assert_eq!(decode(&recorder.to_bytes()).unwrap().records, Vec::new());
+  }
+
+  #[test]
+  fn csv() {
+    let mut csv = alloc::string::String::new();
+    write_csv(&[motion(1), power(1)], &mut csv).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(
+      lines[1],
+      "10000,0.0100,-0.4990,1.0000,1.50,-0.25,99.00,30.01,,,,"
+    );
+    assert_eq!(lines[2], "15000,,,,,,,,4.099,-45.3,0.000,0.0");
+  }
+}
@@ -0,0 +1,196 @@
+use embedded_hal::delay::DelayUs;
+
+use crate::vector::{Acceleration, AngularRate, Vector3};
+
+pub(crate) const SH200Q_ADDRESS: u8 = 0x6C;
+const SH200Q_OUTPUT_ACC: u8 = 0x00;
+const SH200Q_OUTPUT_GYRO: u8 = 0x06;
+const SH200Q_OUTPUT_TEMP: u8 = 0x0C;
+const SH200Q_ACC_CONFIG: u8 = 0x0E;
+const SH200Q_GYRO_CONFIG: u8 = 0x0F;
+const SH200Q_GYRO_DLPF: u8 = 0x11;
+const SH200Q_FIFO_CONFIG: u8 = 0x12;
+const SH200Q_ACC_RANGE: u8 = 0x16;
+const SH200Q_GYRO_RANGE: u8 = 0x2B;
+pub(crate) const SH200Q_WHOAMI: u8 = 0x30;
+const SH200Q_REG_SET1: u8 = 0xBA;
+const SH200Q_ADC_RESET: u8 = 0xC2;
+const SH200Q_REG_SET2: u8 = 0xCA;
+
+/// Value of the WHOAMI register of an SH200Q.
+pub(crate) const SH200Q_ID: u8 = 0x18;
+
+#[derive(Debug)]
+pub enum Error<E> {
+  /// I2C bus error
+  Bus(E),
+  /// An argument was outside of the range supported by the SH200Q
+  InvalidArgument,
+  /// WHOAMI did not identify an SH200Q
+  UnexpectedDeviceId { found: u8 },
+  /// Data was requested before `SH200Q::init`
+  NotInitialized,
+}
+
+impl<E> From<E> for Error<E> {
+  fn from(error: E) -> Self {
+    Error::Bus(error)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ascale {
+  Afs4g = 0,
+  Afs8g = 1,
+  Afs16g = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gscale {
+  Gfs2000dps = 0,
+  Gfs1000dps = 1,
+  Gfs500dps = 2,
+  Gfs250dps = 3,
+  Gfs125dps = 4,
+}
+
+/// IMU of early M5StickC units, in place of the MPU6886.
+pub struct SH200Q<I2C> {
+  i2c: I2C,
+  g_res: f32,
+  a_res: f32,
+  initialized: bool,
+}
+
+impl<I2C> SH200Q<I2C>
+where
+  I2C: embedded_hal::i2c::I2c,
+{
+  pub fn new(i2c: I2C) -> Self {
+    Self {
+      i2c,
+      g_res: 0.0,
+      a_res: 0.0,
+      initialized: false,
+    }
+  }
+
+  /// Reset the sensor and configure 256Hz accelerometer and 500Hz gyroscope output with ±8g and
+  /// ±2000dps ranges.
+  pub fn init<D: DelayUs>(&mut self, delay: &mut D) -> Result<(), Error<I2C::Error>> {
+    let id = self.read_register(SH200Q_WHOAMI)?;
+    if id != SH200Q_ID {
+      return Err(Error::UnexpectedDeviceId { found: id });
+    }
+
+    // ADC reset
+    let adc_reset = self.read_register(SH200Q_ADC_RESET)?;
+    self.write_register(SH200Q_ADC_RESET, adc_reset | 0x04)?;
+    delay.delay_ms(1);
+    self.write_register(SH200Q_ADC_RESET, adc_reset & !0x04)?;
+
+    // Undocumented reset sequence from the vendor's driver
+    let value = self.read_register(0xD8)?;
+    self.write_register(0xD8, value | 0x80)?;
+    delay.delay_ms(1);
+    self.write_register(0xD8, value & !0x80)?;
+    self.write_register(0x78, 0x61)?;
+    delay.delay_ms(1);
+    self.write_register(0x78, 0x00)?;
+
+    // Accelerometer at 256Hz, gyroscope at 500Hz with a 50Hz low pass filter, no FIFO
+    self.write_register(SH200Q_ACC_CONFIG, 0x91)?;
+    self.write_register(SH200Q_GYRO_CONFIG, 0x13)?;
+    self.write_register(SH200Q_GYRO_DLPF, 0x03)?;
+    self.write_register(SH200Q_FIFO_CONFIG, 0x00)?;
+    self.set_accel_fsr(Ascale::Afs8g)?;
+    self.set_gyro_fsr(Gscale::Gfs2000dps)?;
+
+    self.write_register(SH200Q_REG_SET1, 0xC0)?;
+    let value = self.read_register(SH200Q_REG_SET2)?;
+    self.write_register(SH200Q_REG_SET2, value | 0x10)?;
+    delay.delay_ms(1);
+    self.write_register(SH200Q_REG_SET2, value & !0x10)?;
+    delay.delay_ms(10);
+
+    self.initialized = true;
+    Ok(())
+  }
+
+  pub fn set_gyro_fsr(&mut self, scale: Gscale) -> Result<(), Error<I2C::Error>> {
+    self.write_register(SH200Q_GYRO_RANGE, scale as u8)?;
+
+    self.g_res = match scale {
+      Gscale::Gfs125dps => 125.0 / 32768.0,
+      Gscale::Gfs250dps => 250.0 / 32768.0,
+      Gscale::Gfs500dps => 500.0 / 32768.0,
+      Gscale::Gfs1000dps => 1000.0 / 32768.0,
+      Gscale::Gfs2000dps => 2000.0 / 32768.0,
+    };
+
+    Ok(())
+  }
+
+  pub fn set_accel_fsr(&mut self, scale: Ascale) -> Result<(), Error<I2C::Error>> {
+    self.write_register(SH200Q_ACC_RANGE, scale as u8)?;
+
+    self.a_res = match scale {
+      Ascale::Afs4g => 4.0 / 32768.0,
+      Ascale::Afs8g => 8.0 / 32768.0,
+      Ascale::Afs16g => 16.0 / 32768.0,
+    };
+
+    Ok(())
+  }
+
+  pub fn get_gyro_data(&mut self) -> Result<AngularRate, Error<I2C::Error>> {
+    let mut buf = [0x00u8; 6];
+    self.read_data(SH200Q_OUTPUT_GYRO, &mut buf)?;
+    Ok(scale_xyz(&buf, self.g_res).into())
+  }
+
+  pub fn get_accel_data(&mut self) -> Result<Acceleration, Error<I2C::Error>> {
+    let mut buf = [0x00u8; 6];
+    self.read_data(SH200Q_OUTPUT_ACC, &mut buf)?;
+    Ok(scale_xyz(&buf, self.a_res).into())
+  }
+
+  /// Die temperature in degrees Celsius.
+  pub fn get_temp_data(&mut self) -> Result<f32, Error<I2C::Error>> {
+    let mut buf = [0x00u8; 2];
+    self.read_data(SH200Q_OUTPUT_TEMP, &mut buf)?;
+    Ok((le_i16(&buf) as f32) / 333.87 + 21.0)
+  }
+
+  fn read_data(&mut self, reg: u8, buf: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+    if !self.initialized {
+      return Err(Error::NotInitialized);
+    }
+    self.i2c.write_read(SH200Q_ADDRESS, &[reg], buf)?;
+    Ok(())
+  }
+
+  fn read_register(&mut self, reg: u8) -> Result<u8, Error<I2C::Error>> {
+    let mut buf = [0x00u8];
+    self.i2c.write_read(SH200Q_ADDRESS, &[reg], &mut buf)?;
+    Ok(buf[0])
+  }
+
+  fn write_register(&mut self, reg: u8, value: u8) -> Result<(), Error<I2C::Error>> {
+    self.i2c.write(SH200Q_ADDRESS, &[reg, value])?;
+    Ok(())
+  }
+}
+
+/// Scale three little-endian words by `res`.
+fn scale_xyz(data: &[u8], res: f32) -> Vector3 {
+  Vector3::new(
+    (le_i16(&data[0..2]) as f32) * res,
+    (le_i16(&data[2..4]) as f32) * res,
+    (le_i16(&data[4..6]) as f32) * res,
+  )
+}
+
+fn le_i16(bytes: &[u8]) -> i16 {
+  i16::from_le_bytes([bytes[0], bytes[1]])
+}
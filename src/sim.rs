@@ -0,0 +1,1575 @@
+//! Register-level simulations of the AXP192, MPU6886 and SH200Q.
+//!
+//! All devices implement `embedded_hal::i2c::I2c`, keep a register file that the drivers can
+//! read and modify and record every transfer, so initialization sequences and scaling can be
+//! checked without hardware. The simulators are cheap handles to shared state: hand a clone to
+//! the driver and keep one to change sensor values and inspect the registers.
+
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use embedded_hal::delay::DelayUs;
+use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource, Operation};
+
+use crate::axp192::Axp192Event;
+use crate::vector::{Acceleration, AngularRate};
+
+/// One transfer seen on the bus.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Transaction {
+  Write(Vec<u8>),
+  /// Bytes returned to the driver.
+  Read(Vec<u8>),
+}
+
+/// Delay that returns immediately.
+pub struct NoDelay;
+
+impl DelayUs for NoDelay {
+  fn delay_us(&mut self, _us: u32) {}
+}
+
+trait Device {
+  const ADDRESS: u8;
+
+  fn write_bytes(&mut self, bytes: &[u8]);
+  fn read_bytes(&mut self, buf: &mut [u8]);
+  fn log(&mut self) -> &mut Vec<Transaction>;
+
+  fn run(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+    if address != Self::ADDRESS {
+      return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+    }
+    for operation in operations {
+      match operation {
+        Operation::Write(bytes) => {
+          self.log().push(Transaction::Write(bytes.to_vec()));
+          self.write_bytes(bytes);
+        }
+        Operation::Read(buf) => {
+          self.read_bytes(buf);
+          self.log().push(Transaction::Read(buf.to_vec()));
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+macro_rules! impl_i2c {
+  ($device:ty) => {
+    impl embedded_hal::i2c::ErrorType for $device {
+      type Error = ErrorKind;
+    }
+
+    impl embedded_hal::i2c::I2c for $device {
+      fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        self
+          .0
+          .borrow_mut()
+          .run(address, &mut [Operation::Read(read)])
+      }
+
+      fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        self
+          .0
+          .borrow_mut()
+          .run(address, &mut [Operation::Write(write)])
+      }
+
+      fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+      ) -> Result<(), Self::Error> {
+        self.0.borrow_mut().run(
+          address,
+          &mut [Operation::Write(write), Operation::Read(read)],
+        )
+      }
+
+      fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+      ) -> Result<(), Self::Error> {
+        self.0.borrow_mut().run(address, operations)
+      }
+    }
+
+    impl $device {
+      pub fn register(&self, reg: u8) -> u8 {
+        self.0.borrow().regs[reg as usize]
+      }
+
+      /// Set a register behind the driver's back, without any write side effects.
+      pub fn set_register(&self, reg: u8, value: u8) {
+        self.0.borrow_mut().regs[reg as usize] = value;
+      }
+
+      pub fn transactions(&self) -> Vec<Transaction> {
+        self.0.borrow().log.clone()
+      }
+
+      /// Payloads of all writes, in order.
+      pub fn writes(&self) -> Vec<Vec<u8>> {
+        self
+          .0
+          .borrow()
+          .log
+          .iter()
+          .filter_map(|transaction| match transaction {
+            Transaction::Write(bytes) => Some(bytes.clone()),
+            Transaction::Read(_) => None,
+          })
+          .collect()
+      }
+
+      pub fn clear_transactions(&self) {
+        self.0.borrow_mut().log.clear();
+      }
+    }
+  };
+}
+
+/// Simulated AXP192.
+///
+/// Writes are register/value pairs like on the real chip, reads auto-increment. Events only latch
+/// in the IRQ status registers while enabled, initially just the power key presses. The status
+/// registers are cleared by writing 1 and setting bit 5 of the coulomb counter control register
+/// zeroes both counters.
+#[derive(Clone, Default)]
+pub struct Axp192Sim(Rc<RefCell<Axp192State>>);
+
+impl Axp192Sim {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Latch an interrupt in the IRQ status registers, if it is enabled.
+  pub fn raise_event(&self, event: Axp192Event) {
+    const ENABLE: [u8; 5] = [0x40, 0x41, 0x42, 0x43, 0x4A];
+    const STATUS: [u8; 5] = [0x44, 0x45, 0x46, 0x47, 0x4D];
+    let (index, bit) = event.position();
+    let mut state = self.0.borrow_mut();
+    if state.regs[ENABLE[index] as usize] & (1 << bit) != 0 {
+      state.regs[STATUS[index] as usize] |= 1 << bit;
+    }
+  }
+
+  /// Battery voltage in V.
+  pub fn set_bat_voltage(&self, voltage: f32) {
+    self.0.borrow_mut().set_adc12(0x78, voltage / 0.0011);
+  }
+
+  /// Battery current in mA, positive while charging.
+  pub fn set_bat_current(&self, current: f32) {
+    let (charge, discharge) = if current >= 0.0 {
+      (current, 0.0)
+    } else {
+      (0.0, -current)
+    };
+    let mut state = self.0.borrow_mut();
+    state.set_adc13(0x7A, charge / 0.5);
+    state.set_adc13(0x7C, discharge / 0.5);
+  }
+
+  /// VBUS voltage in V.
+  pub fn set_vbus_voltage(&self, voltage: f32) {
+    self.0.borrow_mut().set_adc12(0x5A, voltage / 0.0017);
+  }
+
+  /// VBUS current in mA.
+  pub fn set_vbus_current(&self, current: f32) {
+    self.0.borrow_mut().set_adc12(0x5C, current / 0.375);
+  }
+
+  /// Die temperature in degrees Celsius.
+  pub fn set_temperature(&self, celsius: f32) {
+    self.0.borrow_mut().set_adc12(0x5E, (celsius + 144.7) / 0.1);
+  }
+
+  /// Raw values of the charge and discharge coulomb counters.
+  pub fn set_coulomb_counters(&self, charge: u32, discharge: u32) {
+    self.0.borrow_mut().set_coulomb_counters(charge, discharge);
+  }
+}
+
+impl_i2c!(Axp192Sim);
+
+struct Axp192State {
+  regs: [u8; 256],
+  pointer: u8,
+  log: Vec<Transaction>,
+}
+
+impl Default for Axp192State {
+  fn default() -> Self {
+    let mut regs = [0x00u8; 256];
+    regs[0x03] = 0x03; // IC type
+    regs[0x12] = 0x01; // DCDC1 on
+    regs[0x33] = 0xc8; // 4.2V, 780mA
+    regs[0x34] = 0x41; // 40min precharge, 8h constant current
+    regs[0x42] = 0x03; // PEK short and long press IRQs
+    Self {
+      regs,
+      pointer: 0,
+      log: Vec::new(),
+    }
+  }
+}
+
+impl Axp192State {
+  fn set_coulomb_counters(&mut self, charge: u32, discharge: u32) {
+    self.regs[0xB0..0xB4].copy_from_slice(&charge.to_be_bytes());
+    self.regs[0xB4..0xB8].copy_from_slice(&discharge.to_be_bytes());
+  }
+
+  fn set_adc12(&mut self, reg: u8, value: f32) {
+    let raw = (value + 0.5).clamp(0.0, 4095.0) as u16;
+    self.regs[reg as usize] = (raw >> 4) as u8;
+    self.regs[reg as usize + 1] = (raw & 0x0f) as u8;
+  }
+
+  fn set_adc13(&mut self, reg: u8, value: f32) {
+    let raw = (value + 0.5).clamp(0.0, 8191.0) as u16;
+    self.regs[reg as usize] = (raw >> 5) as u8;
+    self.regs[reg as usize + 1] = (raw & 0x1f) as u8;
+  }
+}
+
+impl Device for Axp192State {
+  const ADDRESS: u8 = 0x34;
+
+  fn write_bytes(&mut self, bytes: &[u8]) {
+    if let [reg] = bytes {
+      self.pointer = *reg;
+    }
+    for pair in bytes.chunks_exact(2) {
+      let (reg, value) = (pair[0], pair[1]);
+      match reg {
+        // IRQ status, write 1 to clear
+        0x44..=0x47 | 0x4D => self.regs[reg as usize] &= !value,
+        0xB8 => {
+          if value & 0x20 != 0 {
+            self.set_coulomb_counters(0, 0);
+          }
+          self.regs[reg as usize] = value & !0x20;
+        }
+        _ => self.regs[reg as usize] = value,
+      }
+    }
+  }
+
+  fn read_bytes(&mut self, buf: &mut [u8]) {
+    for byte in buf {
+      *byte = self.regs[self.pointer as usize];
+      self.pointer = self.pointer.wrapping_add(1);
+    }
+  }
+
+  fn log(&mut self) -> &mut Vec<Transaction> {
+    &mut self.log
+  }
+}
+
+/// Simulated MPU6886.
+///
+/// Writes and reads auto-increment from the register given in the first written byte. Setting
+/// DEVICE_RESET in PWR_MGMT_1 restores the power-on values of all but the sensor output registers
+/// and reading INT_STATUS clears it. The FIFO is filled by `sample_fifo` and drained through
+/// FIFO_R_W. Every new accelerometer reading latches the enabled data-ready and wake-on-motion
+/// interrupts. Enabling the self-test of an axis adds its `set_self_test_response` to the output.
+#[derive(Clone, Default)]
+pub struct Mpu6886Sim(Rc<RefCell<Mpu6886State>>);
+
+impl Mpu6886Sim {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Push the current sensor outputs into the FIFO, like the chip does on every sample.
+  ///
+  /// Nothing happens unless the FIFO is enabled in USER_CTRL. A full FIFO drops the sample when
+  /// FIFO_MODE is set in CONFIG and the oldest bytes otherwise.
+  pub fn sample_fifo(&self) {
+    self.0.borrow_mut().sample_fifo();
+  }
+
+  /// Bytes currently in the FIFO.
+  pub fn fifo_len(&self) -> usize {
+    self.0.borrow().fifo.len()
+  }
+
+  /// Factory accelerometer offsets in 0.98mg, restored on every reset.
+  pub fn set_accel_trim(&self, trim: [i16; 3]) {
+    let mut state = self.0.borrow_mut();
+    for (i, value) in trim.iter().enumerate() {
+      let at = i * 3;
+      state.accel_trim[at..at + 2].copy_from_slice(&(value << 1).to_be_bytes());
+    }
+    let accel_trim = state.accel_trim;
+    state.regs[0x77..0x7F].copy_from_slice(&accel_trim);
+  }
+
+  /// Factory self-test codes of the accelerometer and gyroscope, X/Y/Z, restored on every reset.
+  pub fn set_self_test_codes(&self, accel: [u8; 3], gyro: [u8; 3]) {
+    let mut state = self.0.borrow_mut();
+    state.self_test_codes = [gyro, accel].concat().try_into().unwrap();
+    state.regs[0x00..0x03].copy_from_slice(&gyro);
+    state.regs[0x0D..0x10].copy_from_slice(&accel);
+  }
+
+  /// Raw change of the accelerometer and gyroscope outputs, X/Y/Z, while the self-test of an axis
+  /// is enabled in ACCEL_CONFIG or GYRO_CONFIG.
+  pub fn set_self_test_response(&self, accel: [i16; 3], gyro: [i16; 3]) {
+    let mut state = self.0.borrow_mut();
+    state.self_test_response = [accel, gyro].concat().try_into().unwrap();
+  }
+
+  /// Level of the INT pin: an enabled interrupt is pending.
+  pub fn interrupt_pending(&self) -> bool {
+    let state = self.0.borrow();
+    state.regs[0x3A] & state.regs[0x38] != 0
+  }
+
+  /// Raw accelerometer output, X/Y/Z.
+  pub fn set_accel_raw(&self, raw: [i16; 3]) {
+    self.0.borrow_mut().set_accel_raw(raw);
+  }
+
+  /// Raw gyroscope output, X/Y/Z.
+  pub fn set_gyro_raw(&self, raw: [i16; 3]) {
+    self.0.borrow_mut().set_gyro_raw(raw);
+  }
+
+  pub fn set_temp_raw(&self, raw: i16) {
+    self.0.borrow_mut().set_temp_raw(raw);
+  }
+
+  /// Accelerometer output, scaled with the full scale range currently set in ACCEL_CONFIG.
+  pub fn set_accel(&self, accel: Acceleration) {
+    self.0.borrow_mut().set_accel(accel.vector().into());
+  }
+
+  /// Gyroscope output, scaled with the full scale range currently set in GYRO_CONFIG.
+  pub fn set_gyro(&self, gyro: AngularRate) {
+    self.0.borrow_mut().set_gyro(gyro.vector().into());
+  }
+
+  /// Die temperature in degrees Celsius.
+  pub fn set_temperature(&self, celsius: f32) {
+    self.0.borrow_mut().set_temperature(celsius);
+  }
+}
+
+impl_i2c!(Mpu6886Sim);
+
+struct Mpu6886State {
+  regs: [u8; 256],
+  pointer: u8,
+  fifo: VecDeque<u8>,
+  /// XA_OFFSET_H to ZA_OFFSET_L as loaded on reset.
+  accel_trim: [u8; 8],
+  /// SELF_TEST_X_GYRO to SELF_TEST_Z_GYRO, then SELF_TEST_X_ACCEL to SELF_TEST_Z_ACCEL.
+  self_test_codes: [u8; 6],
+  /// Accelerometer, then gyroscope.
+  self_test_response: [i16; 6],
+  log: Vec<Transaction>,
+}
+
+impl Mpu6886State {
+  const FIFO_SIZE: usize = 1024;
+
+  fn sample_fifo(&mut self) {
+    let fifo_en = self.regs[0x23];
+    if self.regs[0x6A] & (1 << 6) == 0 || fifo_en & 0x18 == 0 {
+      return;
+    }
+
+    let mut packet = Vec::new();
+    if fifo_en & (1 << 3) != 0 {
+      packet.extend_from_slice(&self.regs[0x3B..0x41]);
+    }
+    packet.extend_from_slice(&self.regs[0x41..0x43]);
+    if fifo_en & (1 << 4) != 0 {
+      packet.extend_from_slice(&self.regs[0x43..0x49]);
+    }
+
+    for byte in packet {
+      if self.fifo.len() == Self::FIFO_SIZE {
+        if self.regs[0x1A] & (1 << 6) != 0 {
+          return;
+        }
+        self.fifo.pop_front();
+      }
+      self.fifo.push_back(byte);
+    }
+  }
+
+  fn reset_values() -> [u8; 256] {
+    let mut regs = [0x00u8; 256];
+    regs[0x6B] = 0x41; // PWR_MGMT_1: sleep
+    regs[0x75] = 0x19; // WHO_AM_I
+    regs
+  }
+
+  fn set_accel_raw(&mut self, raw: [i16; 3]) {
+    let int_enable = self.regs[0x38];
+    let mut status = int_enable & 0x01;
+    if self.regs[0x69] & (1 << 7) != 0 {
+      let lsb_per_g = 16384 >> ((self.regs[0x1C] >> 3) & 0x03);
+      for (axis, &value) in raw.iter().enumerate() {
+        let previous = i16::from_be_bytes([self.regs[0x3B + axis * 2], self.regs[0x3C + axis * 2]]);
+        let delta_mg = (value as i32 - previous as i32).abs() * 1000 / lsb_per_g;
+        let threshold_mg = self.regs[0x20 + axis] as i32 * 4;
+        if delta_mg > threshold_mg {
+          status |= (1 << (7 - axis)) & int_enable;
+        }
+      }
+    }
+    self.regs[0x3A] |= status;
+    self.set_words(0x3B, &raw);
+  }
+
+  fn set_gyro_raw(&mut self, raw: [i16; 3]) {
+    self.set_words(0x43, &raw);
+  }
+
+  fn set_temp_raw(&mut self, raw: i16) {
+    self.set_words(0x41, &[raw]);
+  }
+
+  fn set_accel(&mut self, g: [f32; 3]) {
+    let lsb_per_g = 16384.0 / (1 << ((self.regs[0x1C] >> 3) & 0x03)) as f32;
+    self.set_accel_raw(g.map(|value| Self::to_raw(value * lsb_per_g)));
+  }
+
+  fn set_gyro(&mut self, dps: [f32; 3]) {
+    let lsb_per_dps = 131.072 / (1 << ((self.regs[0x1B] >> 3) & 0x03)) as f32;
+    self.set_gyro_raw(dps.map(|value| Self::to_raw(value * lsb_per_dps)));
+  }
+
+  fn set_temperature(&mut self, celsius: f32) {
+    self.set_temp_raw(Self::to_raw((celsius - 25.0) * 326.8));
+  }
+
+  fn to_raw(value: f32) -> i16 {
+    let value = if value < 0.0 {
+      value - 0.5
+    } else {
+      value + 0.5
+    };
+    value.clamp(i16::MIN as f32, i16::MAX as f32) as i16
+  }
+
+  fn set_words(&mut self, reg: u8, words: &[i16]) {
+    for (i, word) in words.iter().enumerate() {
+      let at = reg as usize + i * 2;
+      self.regs[at..at + 2].copy_from_slice(&word.to_be_bytes());
+    }
+  }
+
+  /// Byte of an accelerometer or gyroscope output register, with the self-test response added.
+  fn output_byte(&self, reg: u8) -> u8 {
+    let (first, config, response) = match reg {
+      0x3B..=0x40 => (0x3B, self.regs[0x1C], &self.self_test_response[0..3]),
+      _ => (0x43, self.regs[0x1B], &self.self_test_response[3..6]),
+    };
+    let axis = (reg - first) as usize / 2;
+    let at = (first as usize) + axis * 2;
+    let mut value = i16::from_be_bytes([self.regs[at], self.regs[at + 1]]);
+    if config & (0x80 >> axis) != 0 {
+      value = value.saturating_add(response[axis]);
+    }
+    value.to_be_bytes()[(reg - first) as usize % 2]
+  }
+}
+
+impl Default for Mpu6886State {
+  fn default() -> Self {
+    Self {
+      regs: Self::reset_values(),
+      pointer: 0,
+      fifo: VecDeque::new(),
+      accel_trim: [0x00; 8],
+      self_test_codes: [0x00; 6],
+      self_test_response: [0; 6],
+      log: Vec::new(),
+    }
+  }
+}
+
+impl Device for Mpu6886State {
+  const ADDRESS: u8 = 0x68;
+
+  fn write_bytes(&mut self, bytes: &[u8]) {
+    let Some((&reg, values)) = bytes.split_first() else {
+      return;
+    };
+    self.pointer = reg;
+    for &value in values {
+      match self.pointer {
+        0x6B if value & 0x80 != 0 => {
+          // The sensor outputs keep following the simulated motion
+          let mut regs = Self::reset_values();
+          regs[0x3B..=0x48].copy_from_slice(&self.regs[0x3B..=0x48]);
+          regs[0x77..0x7F].copy_from_slice(&self.accel_trim);
+          regs[0x00..0x03].copy_from_slice(&self.self_test_codes[0..3]);
+          regs[0x0D..0x10].copy_from_slice(&self.self_test_codes[3..6]);
+          self.regs = regs;
+          self.fifo.clear();
+        }
+        // FIFO_RST clears itself
+        0x6A => {
+          if value & (1 << 2) != 0 {
+            self.fifo.clear();
+          }
+          self.regs[0x6A] = value & !(1 << 2);
+        }
+        // WHO_AM_I and the sensor outputs are read only
+        0x3A..=0x48 | 0x75 => {}
+        reg => self.regs[reg as usize] = value,
+      }
+      self.pointer = self.pointer.wrapping_add(1);
+    }
+  }
+
+  fn read_bytes(&mut self, buf: &mut [u8]) {
+    for byte in buf {
+      if self.pointer == 0x74 {
+        // FIFO_R_W does not advance the register pointer
+        *byte = self.fifo.pop_front().unwrap_or(0xff);
+        continue;
+      }
+      *byte = match self.pointer {
+        0x3A => core::mem::take(&mut self.regs[0x3A]),
+        reg @ (0x3B..=0x40 | 0x43..=0x48) => self.output_byte(reg),
+        0x72 => (self.fifo.len() >> 8) as u8,
+        0x73 => self.fifo.len() as u8,
+        reg => self.regs[reg as usize],
+      };
+      self.pointer = self.pointer.wrapping_add(1);
+    }
+  }
+
+  fn log(&mut self) -> &mut Vec<Transaction> {
+    &mut self.log
+  }
+}
+
+/// Simulated SH200Q.
+///
+/// Writes and reads auto-increment from the register given in the first written byte. The sensor
+/// outputs are little-endian like on the real chip.
+#[derive(Clone, Default)]
+pub struct Sh200qSim(Rc<RefCell<Sh200qState>>);
+
+impl Sh200qSim {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Raw accelerometer output, X/Y/Z.
+  pub fn set_accel_raw(&self, raw: [i16; 3]) {
+    self.0.borrow_mut().set_words(0x00, &raw);
+  }
+
+  /// Raw gyroscope output, X/Y/Z.
+  pub fn set_gyro_raw(&self, raw: [i16; 3]) {
+    self.0.borrow_mut().set_words(0x06, &raw);
+  }
+
+  pub fn set_temp_raw(&self, raw: i16) {
+    self.0.borrow_mut().set_words(0x0C, &[raw]);
+  }
+
+  /// Accelerometer output, scaled with the full scale range currently set in ACC_RANGE.
+  pub fn set_accel(&self, accel: Acceleration) {
+    let lsb_per_g = 8192.0 / (1 << (self.register(0x16) & 0x03)) as f32;
+    let raw: [f32; 3] = accel.vector().into();
+    self.set_accel_raw(raw.map(|value| Mpu6886State::to_raw(value * lsb_per_g)));
+  }
+
+  /// Gyroscope output, scaled with the full scale range currently set in GYRO_RANGE.
+  pub fn set_gyro(&self, gyro: AngularRate) {
+    let lsb_per_dps = 16.384 * (1 << (self.register(0x2B) & 0x07).min(4)) as f32;
+    let raw: [f32; 3] = gyro.vector().into();
+    self.set_gyro_raw(raw.map(|value| Mpu6886State::to_raw(value * lsb_per_dps)));
+  }
+
+  /// Die temperature in degrees Celsius.
+  pub fn set_temperature(&self, celsius: f32) {
+    self.set_temp_raw(Mpu6886State::to_raw((celsius - 21.0) * 333.87));
+  }
+}
+
+impl_i2c!(Sh200qSim);
+
+struct Sh200qState {
+  regs: [u8; 256],
+  pointer: u8,
+  log: Vec<Transaction>,
+}
+
+impl Sh200qState {
+  fn set_words(&mut self, reg: u8, words: &[i16]) {
+    for (i, word) in words.iter().enumerate() {
+      let at = reg as usize + i * 2;
+      self.regs[at..at + 2].copy_from_slice(&word.to_le_bytes());
+    }
+  }
+}
+
+impl Default for Sh200qState {
+  fn default() -> Self {
+    let mut regs = [0x00u8; 256];
+    regs[0x30] = 0x18; // WHOAMI
+    Self {
+      regs,
+      pointer: 0,
+      log: Vec::new(),
+    }
+  }
+}
+
+impl Device for Sh200qState {
+  const ADDRESS: u8 = 0x6C;
+
+  fn write_bytes(&mut self, bytes: &[u8]) {
+    let Some((&reg, values)) = bytes.split_first() else {
+      return;
+    };
+    self.pointer = reg;
+    for &value in values {
+      match self.pointer {
+        // WHOAMI and the sensor outputs are read only
+        0x00..=0x0D | 0x30 => {}
+        reg => self.regs[reg as usize] = value,
+      }
+      self.pointer = self.pointer.wrapping_add(1);
+    }
+  }
+
+  fn read_bytes(&mut self, buf: &mut [u8]) {
+    for byte in buf {
+      *byte = self.regs[self.pointer as usize];
+      self.pointer = self.pointer.wrapping_add(1);
+    }
+  }
+
+  fn log(&mut self) -> &mut Vec<Transaction> {
+    &mut self.log
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::axp192::{
+    Axp192, Axp192Events, ChargeConfig, ChargeCurrent, ChargeState, ChargeVoltage,
+    ConstantCurrentTimeout, Error as AxpError, PowerRail, PrechargeTimeout, TerminationCurrent,
+  };
+  use crate::calibration::Calibration;
+  use crate::imu::{AccelRange, AnyImu, Error as ImuError, GyroRange, Imu};
+  use crate::mpu6886::{
+    AccelAveraging, AccelDlpf, Ascale, Error as Mpu6886Error, FifoSample, FifoSensors, GyroDlpf,
+    Mpu6886Config, PowerMode, MPU6886,
+  };
+  use crate::sh200q::{Error as Sh200qError, SH200Q};
+  use crate::vector::Vector3;
+  use alloc::vec;
+
+  #[test]
+  fn axp192_new_powers_the_board() {
+    let sim = Axp192Sim::new();
+    let mut axp = Axp192::new(sim.clone()).unwrap();
+
+    // LDO2 and LDO3 at 3.0V, all ADCs on
+    assert_eq!(sim.register(0x28), 0xCC);
+    assert_eq!(sim.register(0x82), 0xFF);
+    // EXTEN, LDO3, LDO2 and DCDC1 on
+    assert_eq!(sim.register(0x12), 0x4D);
+    assert_eq!(sim.register(0x36), 0x0C);
+    let ldo0 = if cfg!(feature = "m5stickc_plus") {
+      3300
+    } else {
+      2800
+    };
+    assert_eq!(sim.register(0x91) >> 4, ((ldo0 - 1800) / 100) as u8);
+    assert_eq!(sim.register(0x90) & 0x07, 0x02);
+    assert_eq!(axp.get_charge_config().unwrap(), ChargeConfig::default());
+    for rail in [
+      PowerRail::Exten,
+      PowerRail::Ldo0,
+      PowerRail::Ldo2,
+      PowerRail::Ldo3,
+      PowerRail::Dcdc1,
+    ] {
+      assert!(axp.is_rail_enabled(rail).unwrap());
+    }
+  }
+
+  #[test]
+  fn axp192_screen_breath() {
+    let sim = Axp192Sim::new();
+    let mut axp = Axp192::new(sim.clone()).unwrap();
+    sim.clear_transactions();
+
+    axp.screen_breath(100).unwrap();
+    // Read-modify-write of the LDO2 voltage, LDO3 is kept
+    assert_eq!(
+      sim.transactions(),
+      vec![
+        Transaction::Write(vec![0x28]),
+        Transaction::Read(vec![0xCC]),
+        Transaction::Write(vec![0x28, 0xEC]),
+      ]
+    );
+
+    axp.screen_breath(0).unwrap();
+    assert_eq!(sim.register(0x28), 0x7C);
+
+    sim.clear_transactions();
+    assert!(matches!(
+      axp.screen_breath(101),
+      Err(AxpError::InvalidArgument)
+    ));
+    assert!(sim.transactions().is_empty());
+  }
+
+  #[test]
+  fn axp192_set_sleep() {
+    let sim = Axp192Sim::new();
+    let mut axp = Axp192::new(sim.clone()).unwrap();
+
+    axp.set_sleep().unwrap();
+    // Short press wakes up, only DCDC1 keeps running
+    assert_ne!(sim.register(0x31) & 0x08, 0);
+    assert_eq!(sim.register(0x12) & 0x5F, 0x01);
+    assert!(!axp.is_rail_enabled(PowerRail::Ldo0).unwrap());
+    assert!(axp.is_rail_enabled(PowerRail::Dcdc1).unwrap());
+  }
+
+  #[test]
+  fn axp192_rail_voltages() {
+    let sim = Axp192Sim::new();
+    let mut axp = Axp192::new(sim.clone()).unwrap();
+
+    for rail in [
+      PowerRail::Dcdc1,
+      PowerRail::Dcdc3,
+      PowerRail::Ldo2,
+      PowerRail::Ldo3,
+      PowerRail::Ldo0,
+    ] {
+      let (min, max, step) = rail.voltage_range().unwrap();
+      for millivolts in [min, min + step, max] {
+        axp.set_rail_voltage(rail, millivolts).unwrap();
+        assert_eq!(axp.get_rail_voltage(rail).unwrap(), millivolts, "{rail:?}");
+      }
+      // Rounded down to the step
+      axp.set_rail_voltage(rail, min + 2 * step - 1).unwrap();
+      assert_eq!(axp.get_rail_voltage(rail).unwrap(), min + step, "{rail:?}");
+
+      sim.clear_transactions();
+      for millivolts in [min - 1, max + 1] {
+        assert!(matches!(
+          axp.set_rail_voltage(rail, millivolts),
+          Err(AxpError::InvalidArgument)
+        ));
+      }
+      assert!(sim.transactions().is_empty());
+    }
+    assert_eq!(PowerRail::Dcdc1.voltage_range(), Some((700, 3500, 25)));
+    assert_eq!(PowerRail::Ldo0.voltage_range(), Some((1800, 3300, 100)));
+    assert!(matches!(
+      axp.set_rail_voltage(PowerRail::Exten, 5000),
+      Err(AxpError::InvalidArgument)
+    ));
+    assert!(matches!(
+      axp.get_rail_voltage(PowerRail::Exten),
+      Err(AxpError::InvalidArgument)
+    ));
+  }
+
+  #[test]
+  fn axp192_rail_voltages_share_registers() {
+    let sim = Axp192Sim::new();
+    let mut axp = Axp192::new(sim.clone()).unwrap();
+
+    // LDO2 and LDO3 share a register, so do LDO0 and the GPIO0 bits above it
+    axp.set_rail_voltage(PowerRail::Ldo2, 3300).unwrap();
+    axp.set_rail_voltage(PowerRail::Ldo3, 1800).unwrap();
+    assert_eq!(sim.register(0x28), 0xF0);
+    sim.set_register(0x91, 0x05);
+    axp.set_rail_voltage(PowerRail::Ldo0, 3300).unwrap();
+    assert_eq!(sim.register(0x91), 0xF5);
+    // Bit 7 of the DCDC registers is kept
+    sim.set_register(0x26, 0x80);
+    axp.set_rail_voltage(PowerRail::Dcdc1, 3300).unwrap();
+    assert_eq!(sim.register(0x26), 0x80 | 104);
+  }
+
+  #[test]
+  fn axp192_rail_enables() {
+    let sim = Axp192Sim::new();
+    let mut axp = Axp192::new(sim.clone()).unwrap();
+    sim.set_register(0x10, 0x05);
+    sim.set_register(0x12, 0x00);
+
+    let rails = [
+      (PowerRail::Dcdc1, 0x01),
+      (PowerRail::Dcdc3, 0x02),
+      (PowerRail::Ldo2, 0x04),
+      (PowerRail::Ldo3, 0x08),
+      (PowerRail::Exten, 0x40),
+    ];
+    for (rail, mask) in rails {
+      axp.set_rail_enabled(rail, true).unwrap();
+      assert_eq!(sim.register(0x12), mask, "{rail:?}");
+      for (other, _) in rails {
+        assert_eq!(axp.is_rail_enabled(other).unwrap(), other == rail);
+      }
+      axp.set_rail_enabled(rail, false).unwrap();
+      assert_eq!(sim.register(0x12), 0x00, "{rail:?}");
+    }
+
+    // LDO0 is GPIO0 in LDO mode, the bits above the function are kept
+    sim.set_register(0x90, 0xF0);
+    axp.set_rail_enabled(PowerRail::Ldo0, true).unwrap();
+    assert_eq!(sim.register(0x90), 0xF2);
+    assert!(axp.is_rail_enabled(PowerRail::Ldo0).unwrap());
+    axp.set_rail_enabled(PowerRail::Ldo0, false).unwrap();
+    assert_eq!(sim.register(0x90), 0xF7);
+    assert!(!axp.is_rail_enabled(PowerRail::Ldo0).unwrap());
+
+    assert_eq!(sim.register(0x12), 0x00);
+    assert_eq!(sim.register(0x10), 0x05);
+  }
+
+  #[test]
+  fn axp192_drain_events() {
+    let sim = Axp192Sim::new();
+    let mut axp = Axp192::new(sim.clone()).unwrap();
+    let raised = [
+      Axp192Event::VbusRemoved,
+      Axp192Event::ChargeFinished,
+      Axp192Event::PekShortPress,
+      Axp192Event::LowBatteryWarning,
+      Axp192Event::TimerTimeout,
+    ];
+    axp.set_enabled_irqs(raised.into_iter().collect()).unwrap();
+    assert_eq!(
+      [0x40, 0x41, 0x42, 0x43, 0x4A].map(|reg| sim.register(reg)),
+      [0x04, 0x04, 0x02, 0x01, 0x80]
+    );
+    assert_eq!(
+      axp.get_enabled_irqs().unwrap().iter().collect::<Vec<_>>(),
+      raised
+    );
+
+    for event in raised {
+      sim.raise_event(event);
+    }
+    assert_eq!(
+      axp.get_pending_events().unwrap().iter().collect::<Vec<_>>(),
+      raised
+    );
+    let events = axp.drain_events().unwrap();
+    assert_eq!(events.iter().collect::<Vec<_>>(), raised);
+    for reg in [0x44, 0x45, 0x46, 0x47, 0x4D] {
+      assert_eq!(sim.register(reg), 0x00, "{reg:#x}");
+    }
+    assert!(axp.drain_events().unwrap().is_empty());
+  }
+
+  #[test]
+  fn axp192_clear_events() {
+    let sim = Axp192Sim::new();
+    let mut axp = Axp192::new(sim.clone()).unwrap();
+    axp.set_enabled_irqs(Axp192Events::all()).unwrap();
+    sim.raise_event(Axp192Event::VbusInserted);
+    sim.raise_event(Axp192Event::BatteryConnected);
+    sim.clear_transactions();
+
+    // Only the given bits are written
+    axp
+      .clear_events([Axp192Event::VbusInserted].into_iter().collect())
+      .unwrap();
+    assert_eq!(sim.writes(), vec![vec![0x44, 0x08]]);
+    let pending = axp.get_pending_events().unwrap();
+    assert_eq!(
+      pending.iter().collect::<Vec<_>>(),
+      [Axp192Event::BatteryConnected]
+    );
+  }
+
+  #[test]
+  fn axp192_masked_events() {
+    let sim = Axp192Sim::new();
+    let mut axp = Axp192::new(sim.clone()).unwrap();
+    assert_eq!(
+      axp.get_enabled_irqs().unwrap().iter().collect::<Vec<_>>(),
+      [Axp192Event::PekShortPress, Axp192Event::PekLongPress]
+    );
+
+    axp
+      .set_irq_enabled(Axp192Event::VbusInserted, true)
+      .unwrap();
+    axp
+      .set_irq_enabled(Axp192Event::PekLongPress, false)
+      .unwrap();
+    assert_eq!(sim.register(0x40), 0x08);
+    assert_eq!(sim.register(0x42), 0x02);
+    sim.raise_event(Axp192Event::VbusInserted);
+    sim.raise_event(Axp192Event::VbusRemoved);
+    sim.raise_event(Axp192Event::PekLongPress);
+    sim.raise_event(Axp192Event::PekShortPress);
+    assert_eq!(
+      axp.drain_events().unwrap().iter().collect::<Vec<_>>(),
+      [Axp192Event::VbusInserted, Axp192Event::PekShortPress]
+    );
+
+    axp.set_enabled_irqs(Axp192Events::empty()).unwrap();
+    for event in Axp192Event::ALL {
+      sim.raise_event(event);
+    }
+    assert!(axp.drain_events().unwrap().is_empty());
+  }
+
+  #[test]
+  fn axp192_charge_config() {
+    let sim = Axp192Sim::new();
+    // External path settings in bits 5-2 of CONTROL2 survive `new`
+    sim.set_register(0x34, 0xFF);
+    let mut axp = Axp192::new(sim.clone()).unwrap();
+    assert_eq!(sim.register(0x33), 0xC0);
+    assert_eq!(sim.register(0x34), 0x7D);
+
+    let config = ChargeConfig {
+      enabled: false,
+      voltage: ChargeVoltage::V4_36,
+      current: ChargeCurrent::Ma1320,
+      termination: TerminationCurrent::Percent15,
+      precharge_timeout: PrechargeTimeout::Min60,
+      constant_current_timeout: ConstantCurrentTimeout::Hour7,
+    };
+    axp.set_charge_config(&config).unwrap();
+    assert_eq!(sim.register(0x33), 0x7F);
+    assert_eq!(sim.register(0x34), 0xFC);
+    assert_eq!(axp.get_charge_config().unwrap(), config);
+
+    let config = ChargeConfig {
+      enabled: true,
+      voltage: ChargeVoltage::V4_15,
+      current: ChargeCurrent::Ma450,
+      termination: TerminationCurrent::Percent10,
+      precharge_timeout: PrechargeTimeout::Min30,
+      constant_current_timeout: ConstantCurrentTimeout::Hour9,
+    };
+    axp.set_charge_config(&config).unwrap();
+    assert_eq!(sim.register(0x33), 0xA4);
+    assert_eq!(sim.register(0x34), 0x3E);
+    assert_eq!(axp.get_charge_config().unwrap(), config);
+  }
+
+  #[test]
+  fn axp192_charge_state() {
+    let sim = Axp192Sim::new();
+    let mut axp = Axp192::new(sim.clone()).unwrap();
+    let mut state = |power: u8, charge: u8, voltage: f32| {
+      sim.set_register(0x00, power);
+      sim.set_register(0x01, charge);
+      sim.set_bat_voltage(voltage);
+      axp.get_charge_state().unwrap()
+    };
+    // VBUS present, battery present, charging
+    assert_eq!(state(0x20, 0x60, 2.9), ChargeState::Precharge);
+    assert_eq!(state(0x20, 0x60, 3.8), ChargeState::ConstantCurrent);
+    assert_eq!(state(0x80, 0x60, 4.18), ChargeState::ConstantVoltage);
+    assert_eq!(state(0x20, 0x20, 4.2), ChargeState::Done);
+    assert_eq!(state(0x00, 0x20, 4.0), ChargeState::NotCharging);
+    assert_eq!(state(0x20, 0x40, 4.0), ChargeState::NotCharging);
+    assert_eq!(state(0x20, 0xE0, 4.0), ChargeState::Fault);
+    assert_eq!(state(0x20, 0x68, 3.0), ChargeState::Fault);
+
+    axp
+      .set_charge_config(&ChargeConfig {
+        enabled: false,
+        ..Default::default()
+      })
+      .unwrap();
+    sim.set_register(0x00, 0x20);
+    sim.set_register(0x01, 0x60);
+    assert_eq!(axp.get_charge_state().unwrap(), ChargeState::NotCharging);
+  }
+
+  #[test]
+  fn mpu6886_init_sequence() {
+    let sim = Mpu6886Sim::new();
+    let mut imu = MPU6886::new(sim.clone());
+    imu.init(&mut NoDelay).unwrap();
+
+    assert_eq!(
+      sim.writes(),
+      vec![
+        vec![0x75],       // WHO_AM_I
+        vec![0x6B, 0x00], // wake up
+        vec![0x6B, 0x80], // reset
+        vec![0x6B, 0x01], // auto select clock
+        vec![0x38, 0x00],
+        vec![0x1C, 0x10], // ±8g
+        vec![0x1B, 0x18], // ±2000dps
+        vec![0x1A, 0x01], // 176Hz gyro DLPF
+        vec![0x19, 0x05], // 1kHz / 6
+        vec![0x1D, 0x00], // 218Hz accel DLPF, 4 samples
+        vec![0x6A, 0x00],
+        vec![0x23, 0x00],
+        vec![0x37, 0x22],
+        vec![0x38, 0x01], // data ready
+      ]
+    );
+    assert_eq!(sim.register(0x6B), 0x01);
+    assert_eq!(sim.register(0x38), 0x01);
+  }
+
+  #[test]
+  fn mpu6886_init_rejects_other_devices() {
+    let sim = Mpu6886Sim::new();
+    sim.set_register(0x75, 0x68);
+    let mut imu = MPU6886::new(sim.clone());
+    assert!(matches!(
+      imu.init(&mut NoDelay),
+      Err(Mpu6886Error::UnexpectedDeviceId { found: 0x68 })
+    ));
+    assert_eq!(sim.writes(), vec![vec![0x75]]);
+  }
+
+  #[test]
+  fn mpu6886_scaling() {
+    let sim = Mpu6886Sim::new();
+    let mut imu = MPU6886::new(sim.clone());
+    imu.init(&mut NoDelay).unwrap();
+
+    // 4096 LSB/g at ±8g, 16.4 LSB/dps at ±2000dps
+    sim.set_accel_raw([4096, -8192, 2048]);
+    sim.set_gyro_raw([16384, -1638, 0]);
+    sim.set_temp_raw(3268);
+    let accel = imu.get_accel_data().unwrap();
+    assert_eq!((accel.x, accel.y, accel.z), (1.0, -2.0, 0.5));
+    let gyro = imu.get_gyro_data().unwrap();
+    assert_eq!(gyro.x, 1000.0);
+    assert!((gyro.y + 99.98).abs() < 0.01);
+    assert!((imu.get_temp_data().unwrap() - 35.0).abs() < 0.01);
+
+    imu.set_accel_fsr(Ascale::Afs2g).unwrap();
+    assert_eq!(sim.register(0x1C), 0x00);
+    sim.set_accel(Acceleration::new(0.0, 0.0, 1.0));
+    assert_eq!(imu.get_accel_data().unwrap().z, 1.0);
+  }
+
+  #[test]
+  fn mpu6886_motion_data_burst() {
+    let sim = Mpu6886Sim::new();
+    let mut imu = MPU6886::new(sim.clone());
+    assert!(matches!(
+      imu.get_motion_data(),
+      Err(Mpu6886Error::NotInitialized)
+    ));
+    imu.init(&mut NoDelay).unwrap();
+
+    sim.set_accel_raw([4096, -8192, 2048]);
+    sim.set_gyro_raw([16384, -1638, 0]);
+    sim.set_temp_raw(3268);
+    sim.clear_transactions();
+    let sample = imu.get_motion_data().unwrap();
+    assert_eq!(sample.accel, Acceleration::new(1.0, -2.0, 0.5));
+    assert_eq!(sample.gyro.x, 1000.0);
+    assert!((sample.gyro.y + 99.98).abs() < 0.01);
+    assert!((sample.temp_c - 35.0).abs() < 0.01);
+
+    // One transfer starting at ACCEL_XOUT_H covers all three sensors
+    let transactions = sim.transactions();
+    assert_eq!(transactions.len(), 2);
+    assert_eq!(transactions[0], Transaction::Write(vec![0x3B]));
+    assert!(matches!(&transactions[1], Transaction::Read(data) if data.len() == 14));
+  }
+
+  #[test]
+  fn mpu6886_fifo_timestamps() {
+    let sim = Mpu6886Sim::new();
+    let mut imu = MPU6886::new(sim.clone());
+    let mut samples = [FifoSample::default(); 4];
+    assert!(matches!(
+      imu.read_fifo(&mut samples),
+      Err(Mpu6886Error::NotInitialized)
+    ));
+
+    // The divider does not apply at the 8kHz internal rate
+    let config = Mpu6886Config::default()
+      .gyro_dlpf(GyroDlpf::Hz250)
+      .sample_rate_divider(9);
+    imu.init_with(&config, &mut NoDelay).unwrap();
+    assert!(matches!(
+      imu.read_fifo(&mut samples),
+      Err(Mpu6886Error::FifoDisabled)
+    ));
+
+    imu.enable_fifo(FifoSensors::AccelGyro).unwrap();
+    for _ in 0..3 {
+      sim.sample_fifo();
+    }
+    assert_eq!(imu.read_fifo(&mut samples).unwrap(), 3);
+    let timestamps: Vec<u64> = samples[..3].iter().map(|s| s.timestamp_us).collect();
+    assert_eq!(timestamps, vec![0, 125, 250]);
+  }
+
+  #[test]
+  fn mpu6886_fifo_timestamps_accel_dlpf_bypass() {
+    let sim = Mpu6886Sim::new();
+    let mut imu = MPU6886::new(sim.clone());
+    let mut samples = [FifoSample::default(); 3];
+    // The accelerometer runs at 4kHz without its filter, the gyroscope at 1kHz / 6
+    let config = Mpu6886Config::default().accel_dlpf(AccelDlpf::Bypass);
+    imu.init_with(&config, &mut NoDelay).unwrap();
+    assert_eq!(sim.register(0x1D) & 0x08, 0x08);
+
+    for (sensors, period) in [
+      (FifoSensors::Accel, 250),
+      (FifoSensors::AccelGyro, 250),
+      (FifoSensors::Gyro, 6000),
+    ] {
+      imu.enable_fifo(sensors).unwrap();
+      for _ in 0..3 {
+        sim.sample_fifo();
+      }
+      assert_eq!(imu.read_fifo(&mut samples).unwrap(), 3);
+      let timestamps: Vec<u64> = samples.iter().map(|s| s.timestamp_us).collect();
+      assert_eq!(timestamps, vec![0, period, 2 * period], "{sensors:?}");
+    }
+  }
+
+  #[test]
+  fn mpu6886_wake_on_motion() {
+    let sim = Mpu6886Sim::new();
+    let mut imu = MPU6886::new(sim.clone());
+    assert!(matches!(
+      imu.enable_wake_on_motion(100),
+      Err(Mpu6886Error::NotInitialized)
+    ));
+    imu.init(&mut NoDelay).unwrap();
+    sim.clear_transactions();
+
+    assert!(matches!(
+      imu.enable_wake_on_motion(1021),
+      Err(Mpu6886Error::InvalidArgument)
+    ));
+    assert!(sim.transactions().is_empty());
+
+    imu.enable_wake_on_motion(102).unwrap();
+    assert_eq!(
+      sim.writes(),
+      vec![
+        vec![0x20, 25],
+        vec![0x21, 25],
+        vec![0x22, 25],
+        vec![0x69, 0xC0],
+        vec![0x38, 0xE1],
+      ]
+    );
+
+    // 102mg round down to a 100mg threshold: 90mg on Z stay below it, 200mg on Y do not
+    sim.set_accel(Acceleration::new(0.0, 0.0, 1.0));
+    imu.get_interrupt_status().unwrap();
+    sim.set_accel(Acceleration::new(0.0, 0.0, 1.09));
+    let status = imu.get_interrupt_status().unwrap();
+    assert!(!status.motion() && status.data_ready());
+    sim.set_accel(Acceleration::new(0.0, 0.2, 1.09));
+    assert!(sim.interrupt_pending());
+    let status = imu.get_interrupt_status().unwrap();
+    assert!(status.motion() && status.motion_y() && !status.motion_x() && !status.motion_z());
+    assert!(!sim.interrupt_pending());
+
+    // Only motion wakes up without data ready
+    imu.set_data_ready_interrupt(false).unwrap();
+    assert_eq!(sim.register(0x38), 0xE0);
+    sim.set_accel(Acceleration::new(0.0, 0.2, 1.1));
+    assert!(!sim.interrupt_pending());
+    sim.set_accel(Acceleration::new(0.5, 0.2, 1.1));
+    assert!(sim.interrupt_pending());
+
+    imu.disable_wake_on_motion().unwrap();
+    assert_eq!(sim.register(0x38), 0x00);
+    assert_eq!(sim.register(0x69), 0x00);
+    imu.set_data_ready_interrupt(true).unwrap();
+    assert_eq!(sim.register(0x38), 0x01);
+  }
+
+  #[test]
+  fn mpu6886_power_modes() {
+    let sim = Mpu6886Sim::new();
+    let mut imu = MPU6886::new(sim.clone());
+    assert!(matches!(
+      imu.set_power_mode(PowerMode::Sleep),
+      Err(Mpu6886Error::NotInitialized)
+    ));
+    let config = Mpu6886Config::default()
+      .accel_dlpf(AccelDlpf::Bypass)
+      .accel_averaging(AccelAveraging::Samples16);
+    imu.init_with(&config, &mut NoDelay).unwrap();
+    sim.set_register(0x1E, 0x80);
+    sim.clear_transactions();
+
+    imu.set_power_mode(PowerMode::Sleep).unwrap();
+    assert_eq!(sim.writes(), vec![vec![0x6B, 0x41]]);
+    assert_eq!(imu.power_mode(), PowerMode::Sleep);
+
+    sim.clear_transactions();
+    imu.set_power_mode(PowerMode::GyroStandby).unwrap();
+    assert_eq!(
+      sim.writes(),
+      vec![vec![0x6B, 0x11], vec![0x6C, 0x00], vec![0x1D, 0x28]]
+    );
+
+    // Averaging kept, filter enabled, gyroscope off and not cycling
+    sim.clear_transactions();
+    imu.set_power_mode(PowerMode::AccelLowPower).unwrap();
+    assert_eq!(
+      sim.writes(),
+      vec![
+        vec![0x1D, 0x20],
+        vec![0x1E, 0x00],
+        vec![0x6C, 0x07],
+        vec![0x6B, 0x21],
+      ]
+    );
+
+    sim.clear_transactions();
+    imu.wake().unwrap();
+    assert_eq!(
+      sim.writes(),
+      vec![vec![0x6B, 0x01], vec![0x6C, 0x00], vec![0x1D, 0x28]]
+    );
+    assert_eq!(imu.power_mode(), PowerMode::Active);
+
+    imu.sleep().unwrap();
+    imu.init(&mut NoDelay).unwrap();
+    assert_eq!(imu.power_mode(), PowerMode::Active);
+  }
+
+  #[test]
+  fn mpu6886_reads_respect_power_mode() {
+    let sim = Mpu6886Sim::new();
+    let mut imu = MPU6886::new(sim.clone());
+    imu.init(&mut NoDelay).unwrap();
+    imu.enable_fifo(FifoSensors::AccelGyro).unwrap();
+    let mut samples = [FifoSample::default(); 4];
+
+    imu.sleep().unwrap();
+    sim.clear_transactions();
+    assert!(matches!(imu.get_accel_data(), Err(Mpu6886Error::Sleeping)));
+    assert!(matches!(imu.get_gyro_data(), Err(Mpu6886Error::Sleeping)));
+    assert!(matches!(imu.get_temp_data(), Err(Mpu6886Error::Sleeping)));
+    assert!(matches!(imu.get_motion_data(), Err(Mpu6886Error::Sleeping)));
+    assert!(matches!(
+      imu.read_fifo(&mut samples),
+      Err(Mpu6886Error::Sleeping)
+    ));
+    assert!(sim.transactions().is_empty());
+
+    for mode in [PowerMode::GyroStandby, PowerMode::AccelLowPower] {
+      imu.set_power_mode(mode).unwrap();
+      sim.clear_transactions();
+      assert!(matches!(
+        imu.get_gyro_data(),
+        Err(Mpu6886Error::GyroDisabled)
+      ));
+      assert!(matches!(
+        imu.get_motion_data(),
+        Err(Mpu6886Error::GyroDisabled)
+      ));
+      assert!(matches!(
+        imu.read_fifo(&mut samples),
+        Err(Mpu6886Error::GyroDisabled)
+      ));
+      assert!(sim.transactions().is_empty());
+      imu.get_accel_data().unwrap();
+      imu.get_temp_data().unwrap();
+    }
+
+    // An accelerometer-only FIFO keeps working without the gyroscope
+    imu.enable_fifo(FifoSensors::Accel).unwrap();
+    sim.sample_fifo();
+    assert_eq!(imu.read_fifo(&mut samples).unwrap(), 1);
+  }
+
+  #[test]
+  fn mpu6886_calibrate_gyro() {
+    let sim = Mpu6886Sim::new();
+    let mut imu = MPU6886::new(sim.clone());
+    imu.init(&mut NoDelay).unwrap();
+    assert!(matches!(
+      imu.calibrate_gyro(0, &mut NoDelay),
+      Err(Mpu6886Error::InvalidArgument)
+    ));
+
+    sim.set_gyro(AngularRate::new(1.5, -2.0, 0.25));
+    let bias = imu.calibrate_gyro(16, &mut NoDelay).unwrap();
+    assert_eq!(imu.calibration().gyro_bias, bias);
+    assert!((bias.x - 1.5).abs() < 0.05);
+    assert!((bias.y + 2.0).abs() < 0.05);
+    assert!((bias.z - 0.25).abs() < 0.05);
+    // The accelerometer part of the calibration is kept
+    assert_eq!(imu.calibration().accel_scale, Vector3::new(1.0, 1.0, 1.0));
+
+    let gyro = imu.get_gyro_data().unwrap();
+    assert!(gyro.x.abs() < 1e-6 && gyro.y.abs() < 1e-6 && gyro.z.abs() < 1e-6);
+  }
+
+  #[test]
+  fn mpu6886_program_offsets() {
+    let sim = Mpu6886Sim::new();
+    sim.set_accel_trim([100, -200, 300]);
+    let mut imu = MPU6886::new(sim.clone());
+    assert!(matches!(
+      imu.program_offsets(),
+      Err(Mpu6886Error::NotInitialized)
+    ));
+    imu.init(&mut NoDelay).unwrap();
+    // Bit 0 of the trim is reserved and has to survive
+    sim.set_register(0x78, sim.register(0x78) | 0x01);
+
+    let calibration = Calibration {
+      gyro_bias: AngularRate::new(1.0, -0.5, 0.0),
+      accel_offset: Acceleration::new(0.0098, 0.0, -0.0098),
+      ..Default::default()
+    };
+    imu.set_calibration(calibration).unwrap();
+    sim.clear_transactions();
+    imu.program_offsets().unwrap();
+    assert_eq!(
+      sim.writes(),
+      vec![
+        vec![0x77], // factory trim
+        // -bias in 32.8 LSB/dps: -33, 16, 0
+        vec![0x13, 0xFF, 0xDF, 0x00, 0x10, 0x00, 0x00],
+        // Trim minus offset in 0.98mg: 90, -200, 310
+        vec![0x77, 0x00],
+        vec![0x78, 0xB5],
+        vec![0x7A, 0xFE],
+        vec![0x7B, 0x70],
+        vec![0x7D, 0x02],
+        vec![0x7E, 0x6C],
+      ]
+    );
+
+    // Changing the calibration reprograms the offsets from the trim read before
+    sim.clear_transactions();
+    imu
+      .set_calibration(Calibration {
+        gyro_bias: AngularRate::ZERO,
+        ..calibration
+      })
+      .unwrap();
+    let writes = sim.writes();
+    assert_eq!(writes.len(), 7);
+    assert_eq!(writes[0], vec![0x13, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(sim.register(0x77), 0x00);
+    assert_eq!(sim.register(0x78), 0xB5);
+
+    // Readings are only scaled once the chip removes the offset
+    sim.set_accel(Acceleration::new(0.5, 0.0, 0.0));
+    assert_eq!(imu.get_accel_data().unwrap().x, 0.5);
+  }
+
+  #[test]
+  fn mpu6886_self_test() {
+    let sim = Mpu6886Sim::new();
+    sim.set_self_test_codes([100; 3], [100; 3]);
+    let mut imu = MPU6886::new(sim.clone());
+    imu.init(&mut NoDelay).unwrap();
+    imu.enable_fifo(FifoSensors::AccelGyro).unwrap();
+    let registers =
+      |sim: &Mpu6886Sim| [0x19, 0x1A, 0x1B, 0x1C, 0x1D, 0x23].map(|reg| sim.register(reg));
+    let before = registers(&sim);
+
+    // Factory response of code 100 is about 7017 LSB, the sign of the response does not matter
+    sim.set_self_test_response([-7000, 7000, 6000], [7000, -5000, 4000]);
+    let result = imu.self_test(&mut NoDelay).unwrap();
+    assert!(result.passed(), "{result:?}");
+    assert!((result.accel[0].response + 7000.0 / 16384.0).abs() < 1e-3);
+    assert_eq!(registers(&sim), before);
+    assert_ne!(sim.register(0x1A) & (1 << 6), 0);
+
+    sim.set_self_test_response([-2000, 7000, 7000], [7000, 7000, -3000]);
+    let result = imu.self_test(&mut NoDelay).unwrap();
+    assert!(!result.accel[0].passed);
+    assert!(result.accel[1].passed);
+    assert!(!result.gyro[2].passed);
+    assert_eq!(registers(&sim), before);
+  }
+
+  #[test]
+  fn sh200q_init_sequence() {
+    let sim = Sh200qSim::new();
+    sim.set_register(0xC2, 0x41);
+    sim.set_register(0xD8, 0x01);
+    sim.set_register(0xCA, 0x20);
+    let mut imu = SH200Q::new(sim.clone());
+    assert!(matches!(
+      imu.get_accel_data(),
+      Err(Sh200qError::NotInitialized)
+    ));
+    imu.init(&mut NoDelay).unwrap();
+
+    assert_eq!(
+      sim.writes(),
+      vec![
+        vec![0x30], // WHOAMI
+        vec![0xC2], // ADC reset
+        vec![0xC2, 0x45],
+        vec![0xC2, 0x41],
+        vec![0xD8],
+        vec![0xD8, 0x81],
+        vec![0xD8, 0x01],
+        vec![0x78, 0x61],
+        vec![0x78, 0x00],
+        vec![0x0E, 0x91], // accelerometer at 256Hz
+        vec![0x0F, 0x13], // gyroscope at 500Hz
+        vec![0x11, 0x03], // 50Hz gyroscope DLPF
+        vec![0x12, 0x00], // no FIFO
+        vec![0x16, 0x01], // ±8g
+        vec![0x2B, 0x00], // ±2000dps
+        vec![0xBA, 0xC0],
+        vec![0xCA],
+        vec![0xCA, 0x30],
+        vec![0xCA, 0x20],
+      ]
+    );
+  }
+
+  #[test]
+  fn sh200q_init_rejects_other_devices() {
+    let sim = Sh200qSim::new();
+    sim.set_register(0x30, 0x19);
+    let mut imu = SH200Q::new(sim.clone());
+    assert!(matches!(
+      imu.init(&mut NoDelay),
+      Err(Sh200qError::UnexpectedDeviceId { found: 0x19 })
+    ));
+    assert_eq!(sim.writes(), vec![vec![0x30]]);
+  }
+
+  #[test]
+  fn sh200q_scaling() {
+    let sim = Sh200qSim::new();
+    let mut imu = SH200Q::new(sim.clone());
+    imu.init(&mut NoDelay).unwrap();
+
+    // 4096 LSB/g at ±8g, 16.384 LSB/dps at ±2000dps, little-endian
+    sim.set_accel_raw([4096, -8192, 2048]);
+    sim.set_gyro_raw([16384, -8192, 0]);
+    sim.set_temp_raw(3339);
+    assert_eq!(sim.register(0x00), 0x00);
+    assert_eq!(sim.register(0x01), 0x10);
+    let accel = imu.get_accel_data().unwrap();
+    assert_eq!((accel.x, accel.y, accel.z), (1.0, -2.0, 0.5));
+    let gyro = imu.get_gyro_data().unwrap();
+    assert_eq!((gyro.x, gyro.y, gyro.z), (1000.0, -500.0, 0.0));
+    assert!((imu.get_temp_data().unwrap() - 31.0).abs() < 0.01);
+
+    sim.set_temperature(-5.0);
+    assert!((imu.get_temp_data().unwrap() + 5.0).abs() < 0.01);
+  }
+
+  #[test]
+  fn sh200q_ranges() {
+    let sim = Sh200qSim::new();
+    let mut imu = SH200Q::new(sim.clone());
+    imu.init(&mut NoDelay).unwrap();
+    sim.clear_transactions();
+
+    // ±2g is not supported and nothing is written
+    assert!(matches!(
+      Imu::set_accel_range(&mut imu, AccelRange::G2),
+      Err(Sh200qError::InvalidArgument)
+    ));
+    assert_eq!(sim.writes(), Vec::<Vec<u8>>::new());
+
+    for (range, value, g) in [
+      (AccelRange::G4, 0x00, 2.0),
+      (AccelRange::G8, 0x01, 4.0),
+      (AccelRange::G16, 0x02, 8.0),
+    ] {
+      Imu::set_accel_range(&mut imu, range).unwrap();
+      assert_eq!(sim.register(0x16), value);
+      sim.set_accel(Acceleration::new(g, -1.0, 0.0));
+      let accel = imu.get_accel_data().unwrap();
+      assert!((accel.x - g).abs() < 1e-3, "{range:?}: {accel:?}");
+      assert!((accel.y + 1.0).abs() < 1e-3, "{range:?}: {accel:?}");
+    }
+
+    for (range, value, dps) in [
+      (GyroRange::Dps125, 0x04, 100.0),
+      (GyroRange::Dps250, 0x03, 200.0),
+      (GyroRange::Dps500, 0x02, 400.0),
+      (GyroRange::Dps1000, 0x01, 800.0),
+      (GyroRange::Dps2000, 0x00, 1600.0),
+    ] {
+      Imu::set_gyro_range(&mut imu, range).unwrap();
+      assert_eq!(sim.register(0x2B), value);
+      sim.set_gyro(AngularRate::new(0.0, dps, -dps));
+      let gyro = imu.get_gyro_data().unwrap();
+      assert!((gyro.y - dps).abs() < 0.1, "{range:?}: {gyro:?}");
+      assert!((gyro.z + dps).abs() < 0.1, "{range:?}: {gyro:?}");
+    }
+  }
+
+  #[test]
+  fn any_imu_probe() {
+    // Each simulator only acknowledges its own address
+    let sim = Sh200qSim::new();
+    let mut imu = AnyImu::probe(sim.clone());
+    assert!(imu.as_sh200q().is_some());
+    assert!(imu.as_mpu6886().is_none());
+    imu.init(&mut NoDelay).unwrap();
+    sim.set_accel(Acceleration::new(0.0, 0.0, 1.0));
+    assert_eq!(imu.get_accel_data().unwrap().z, 1.0);
+    assert!(matches!(
+      imu.set_accel_range(AccelRange::G2),
+      Err(ImuError::Sh200q(Sh200qError::InvalidArgument))
+    ));
+
+    let sim = Mpu6886Sim::new();
+    let mut imu = AnyImu::probe(sim.clone());
+    assert!(imu.as_mpu6886().is_some());
+    assert!(imu.as_sh200q().is_none());
+    imu.init(&mut NoDelay).unwrap();
+    assert!(matches!(
+      imu.set_gyro_range(GyroRange::Dps125),
+      Err(ImuError::Mpu6886(Mpu6886Error::InvalidArgument))
+    ));
+    imu.set_accel_range(AccelRange::G2).unwrap();
+    assert_eq!(sim.register(0x1C), 0x00);
+
+    // A device answering at the address is no IMU without the right WHOAMI
+    let sim = Sh200qSim::new();
+    sim.set_register(0x30, 0x00);
+    let mut imu = AnyImu::probe(sim.clone());
+    assert!(imu.as_mpu6886().is_some());
+
+    // Nothing answers: the fallback MPU6886 reports the problem on init
+    let mut imu = AnyImu::probe(Axp192Sim::new());
+    assert!(imu.as_mpu6886().is_some());
+    assert!(matches!(
+      imu.init(&mut NoDelay),
+      Err(ImuError::Mpu6886(Mpu6886Error::Bus(
+        ErrorKind::NoAcknowledge(_)
+      )))
+    ));
+  }
+}
@@ -0,0 +1,104 @@
+use alloc::rc::Rc;
+use core::cell::Cell;
+use core::ops::{Add, Sub};
+use core::time::Duration;
+
+/// Point in time in microseconds since an arbitrary start, e.g. boot.
+///
+/// 64 bits of microseconds do not wrap for over 500000 years, so comparisons and differences need
+/// no wrap-around handling.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+  pub const fn from_micros(us: u64) -> Self {
+    Self(us)
+  }
+
+  pub const fn from_millis(ms: u64) -> Self {
+    Self(ms * 1000)
+  }
+
+  pub const fn as_micros(self) -> u64 {
+    self.0
+  }
+
+  pub const fn as_millis(self) -> u64 {
+    self.0 / 1000
+  }
+
+  /// Time from `earlier` to `self`, 0 if `earlier` is later.
+  pub fn saturating_duration_since(self, earlier: Instant) -> Duration {
+    Duration::from_micros(self.0.saturating_sub(earlier.0))
+  }
+}
+
+impl Add<Duration> for Instant {
+  type Output = Instant;
+
+  fn add(self, duration: Duration) -> Instant {
+    Instant(self.0 + duration.as_micros() as u64)
+  }
+}
+
+impl Sub<Instant> for Instant {
+  type Output = Duration;
+
+  /// Same as `saturating_duration_since`.
+  fn sub(self, earlier: Instant) -> Duration {
+    self.saturating_duration_since(earlier)
+  }
+}
+
+/// Monotonic time source.
+pub trait Clock {
+  fn now(&self) -> Instant;
+}
+
+/// `esp_timer_get_time` on the device, which can also be read in interrupt handlers. On a host,
+/// time since the first call.
+#[cfg(any(feature = "esp_idf", feature = "std"))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "esp_idf")]
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::from_micros(unsafe { esp_idf_sys::esp_timer_get_time() } as u64)
+  }
+}
+
+#[cfg(all(feature = "std", not(feature = "esp_idf")))]
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    let elapsed = START.get_or_init(std::time::Instant::now).elapsed();
+    Instant::from_micros(elapsed.as_micros() as u64)
+  }
+}
+
+/// Clock that only moves when told to, for driving time-dependent code deterministically.
+///
+/// Clones share the time, so the code under test can own one while the test advances another.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock(Rc<Cell<Instant>>);
+
+impl ManualClock {
+  pub fn new(now: Instant) -> Self {
+    Self(Rc::new(Cell::new(now)))
+  }
+
+  pub fn set(&self, now: Instant) {
+    self.0.set(now);
+  }
+
+  pub fn advance(&self, duration: Duration) {
+    self.0.set(self.0.get() + duration);
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Instant {
+    self.0.get()
+  }
+}
@@ -0,0 +1,385 @@
+use core::ops::{Add, Mul, Neg, Sub};
+
+use libm::sqrtf;
+
+/// Standard gravity in m/s².
+pub const STANDARD_GRAVITY: f32 = 9.80665;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vector3 {
+  pub x: f32,
+  pub y: f32,
+  pub z: f32,
+}
+
+impl Vector3 {
+  pub const ZERO: Vector3 = Vector3::new(0.0, 0.0, 0.0);
+
+  pub const fn new(x: f32, y: f32, z: f32) -> Self {
+    Self { x, y, z }
+  }
+
+  pub fn norm(self) -> f32 {
+    sqrtf(self.dot(self))
+  }
+
+  /// Unit vector in the same direction, `None` for the zero vector.
+  pub fn normalized(self) -> Option<Self> {
+    let norm = self.norm();
+    if norm == 0.0 {
+      return None;
+    }
+    Some(self * (1.0 / norm))
+  }
+
+  pub fn dot(self, other: Self) -> f32 {
+    self.x * other.x + self.y * other.y + self.z * other.z
+  }
+
+  pub fn cross(self, other: Self) -> Self {
+    Self {
+      x: self.y * other.z - self.z * other.y,
+      y: self.z * other.x - self.x * other.z,
+      z: self.x * other.y - self.y * other.x,
+    }
+  }
+
+  /// Multiply component by component.
+  pub fn scale(self, factors: Self) -> Self {
+    Self {
+      x: self.x * factors.x,
+      y: self.y * factors.y,
+      z: self.z * factors.z,
+    }
+  }
+
+  pub fn map(self, f: impl Fn(f32) -> f32) -> Self {
+    Self {
+      x: f(self.x),
+      y: f(self.y),
+      z: f(self.z),
+    }
+  }
+}
+
+impl Add for Vector3 {
+  type Output = Self;
+
+  fn add(self, other: Self) -> Self {
+    Self::new(self.x + other.x, self.y + other.y, self.z + other.z)
+  }
+}
+
+impl Sub for Vector3 {
+  type Output = Self;
+
+  fn sub(self, other: Self) -> Self {
+    Self::new(self.x - other.x, self.y - other.y, self.z - other.z)
+  }
+}
+
+impl Mul<f32> for Vector3 {
+  type Output = Self;
+
+  fn mul(self, factor: f32) -> Self {
+    self.map(|value| value * factor)
+  }
+}
+
+impl Neg for Vector3 {
+  type Output = Self;
+
+  fn neg(self) -> Self {
+    self.map(|value| -value)
+  }
+}
+
+impl From<(f32, f32, f32)> for Vector3 {
+  fn from((x, y, z): (f32, f32, f32)) -> Self {
+    Self::new(x, y, z)
+  }
+}
+
+impl From<[f32; 3]> for Vector3 {
+  fn from([x, y, z]: [f32; 3]) -> Self {
+    Self::new(x, y, z)
+  }
+}
+
+impl From<Vector3> for (f32, f32, f32) {
+  fn from(v: Vector3) -> Self {
+    (v.x, v.y, v.z)
+  }
+}
+
+impl From<Vector3> for [f32; 3] {
+  fn from(v: Vector3) -> Self {
+    [v.x, v.y, v.z]
+  }
+}
+
+macro_rules! unit_vector {
+  ($(#[$doc:meta])* $name:ident) => {
+    $(#[$doc])*
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub struct $name {
+      pub x: f32,
+      pub y: f32,
+      pub z: f32,
+    }
+
+    impl $name {
+      pub const ZERO: $name = $name::new(0.0, 0.0, 0.0);
+
+      pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+      }
+
+      pub fn vector(self) -> Vector3 {
+        Vector3::new(self.x, self.y, self.z)
+      }
+
+      pub fn norm(self) -> f32 {
+        self.vector().norm()
+      }
+    }
+
+    impl From<Vector3> for $name {
+      fn from(v: Vector3) -> Self {
+        Self::new(v.x, v.y, v.z)
+      }
+    }
+
+    impl From<$name> for Vector3 {
+      fn from(v: $name) -> Self {
+        v.vector()
+      }
+    }
+
+    impl Add for $name {
+      type Output = Self;
+
+      fn add(self, other: Self) -> Self {
+        (self.vector() + other.vector()).into()
+      }
+    }
+
+    impl Sub for $name {
+      type Output = Self;
+
+      fn sub(self, other: Self) -> Self {
+        (self.vector() - other.vector()).into()
+      }
+    }
+
+    impl Mul<f32> for $name {
+      type Output = Self;
+
+      fn mul(self, factor: f32) -> Self {
+        (self.vector() * factor).into()
+      }
+    }
+
+    impl Neg for $name {
+      type Output = Self;
+
+      fn neg(self) -> Self {
+        (-self.vector()).into()
+      }
+    }
+  };
+}
+
+unit_vector!(
+  /// Acceleration in g.
+  Acceleration
+);
+
+unit_vector!(
+  /// Angular rate in degrees per second.
+  AngularRate
+);
+
+impl Acceleration {
+  pub fn from_m_per_s2(v: Vector3) -> Self {
+    (v * (1.0 / STANDARD_GRAVITY)).into()
+  }
+
+  pub fn in_g(self) -> Vector3 {
+    self.vector()
+  }
+
+  pub fn in_m_per_s2(self) -> Vector3 {
+    self.vector() * STANDARD_GRAVITY
+  }
+}
+
+impl AngularRate {
+  pub fn from_rad_per_s(v: Vector3) -> Self {
+    v.map(f32::to_degrees).into()
+  }
+
+  pub fn in_dps(self) -> Vector3 {
+    self.vector()
+  }
+
+  pub fn in_rad_per_s(self) -> Vector3 {
+    self.vector().map(f32::to_radians)
+  }
+}
+
+/// One axis of the source frame, possibly inverted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+  X,
+  Y,
+  Z,
+  NegX,
+  NegY,
+  NegZ,
+}
+
+impl Axis {
+  fn pick(self, v: Vector3) -> f32 {
+    match self {
+      Axis::X => v.x,
+      Axis::Y => v.y,
+      Axis::Z => v.z,
+      Axis::NegX => -v.x,
+      Axis::NegY => -v.y,
+      Axis::NegZ => -v.z,
+    }
+  }
+}
+
+/// Rotation between frames that only swaps and inverts axes, e.g. from the IMU chip to the board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AxisRemap {
+  /// Source axis that becomes X.
+  pub x: Axis,
+  pub y: Axis,
+  pub z: Axis,
+}
+
+impl AxisRemap {
+  pub const IDENTITY: AxisRemap = AxisRemap {
+    x: Axis::X,
+    y: Axis::Y,
+    z: Axis::Z,
+  };
+
+  /// From the MPU6886 to the LCD of the StickC as set up by `M5`, which is in landscape: X to the
+  /// right, Y to the top of the screen and Z out of it.
+  pub const STICKC: AxisRemap = AxisRemap {
+    x: Axis::NegY,
+    y: Axis::X,
+    z: Axis::Z,
+  };
+
+  /// From the MPU6886 to the LCD of the StickC Plus as set up by `M5`, which is in portrait: X to
+  /// the right, Y to the top of the screen and Z out of it.
+  pub const STICKC_PLUS: AxisRemap = AxisRemap::IDENTITY;
+
+  /// From the MPU6886 to the LCD of the board selected by the crate features.
+  #[cfg(not(feature = "m5stickc_plus"))]
+  pub const LCD: AxisRemap = AxisRemap::STICKC;
+  /// From the MPU6886 to the LCD of the board selected by the crate features.
+  #[cfg(feature = "m5stickc_plus")]
+  pub const LCD: AxisRemap = AxisRemap::STICKC_PLUS;
+
+  pub fn apply<V>(&self, v: V) -> V
+  where
+    V: From<Vector3>,
+    Vector3: From<V>,
+  {
+    let v = Vector3::from(v);
+    Vector3::new(self.x.pick(v), self.y.pick(v), self.z.pick(v)).into()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn close(a: Vector3, b: Vector3) -> bool {
+    (a - b).norm() < 1e-5
+  }
+
+  /// Where sensor +X, +Y and +Z end up.
+  fn images(remap: AxisRemap) -> [Vector3; 3] {
+    [
+      Vector3::new(1.0, 0.0, 0.0),
+      Vector3::new(0.0, 1.0, 0.0),
+      Vector3::new(0.0, 0.0, 1.0),
+    ]
+    .map(|v| remap.apply(v))
+  }
+
+  #[test]
+  fn stickc_remap() {
+    // Sensor X points to the top of the landscape screen, sensor Y to its left
+    assert_eq!(
+      images(AxisRemap::STICKC),
+      [
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(-1.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, 1.0),
+      ]
+    );
+    // Remapping keeps the type
+    let accel = AxisRemap::STICKC.apply(Acceleration::new(0.1, 0.2, 0.3));
+    assert_eq!(accel, Acceleration::new(-0.2, 0.1, 0.3));
+  }
+
+  #[test]
+  fn stickc_plus_remap() {
+    assert_eq!(
+      images(AxisRemap::STICKC_PLUS),
+      [
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(0.0, 0.0, 1.0),
+      ]
+    );
+  }
+
+  #[test]
+  fn lcd_remap_follows_the_board() {
+    #[cfg(not(feature = "m5stickc_plus"))]
+    assert_eq!(AxisRemap::LCD, AxisRemap::STICKC);
+    #[cfg(feature = "m5stickc_plus")]
+    assert_eq!(AxisRemap::LCD, AxisRemap::STICKC_PLUS);
+  }
+
+  #[test]
+  fn acceleration_units() {
+    let accel = Acceleration::new(1.0, -0.5, 2.0);
+    assert!(close(
+      accel.in_m_per_s2(),
+      Vector3::new(STANDARD_GRAVITY, -4.903325, 19.6133)
+    ));
+    assert_eq!(accel.in_g(), accel.vector());
+    let round_trip = Acceleration::from_m_per_s2(accel.in_m_per_s2());
+    assert!(close(round_trip.vector(), accel.vector()));
+    assert!(close(
+      Acceleration::from_m_per_s2(Vector3::new(0.0, 0.0, STANDARD_GRAVITY)).vector(),
+      Vector3::new(0.0, 0.0, 1.0)
+    ));
+  }
+
+  #[test]
+  fn angular_rate_units() {
+    let rate = AngularRate::new(180.0, -90.0, 45.0);
+    assert!(close(
+      rate.in_rad_per_s(),
+      Vector3::new(
+        core::f32::consts::PI,
+        -core::f32::consts::FRAC_PI_2,
+        core::f32::consts::FRAC_PI_4
+      )
+    ));
+    assert_eq!(rate.in_dps(), rate.vector());
+    let round_trip = AngularRate::from_rad_per_s(rate.in_rad_per_s());
+    assert!(close(round_trip.vector(), rate.vector()));
+  }
+}